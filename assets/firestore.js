@@ -1,5 +1,5 @@
 import { initializeApp } from 'https://www.gstatic.com/firebasejs/9.6.1/firebase-app.js';
-import { getFirestore, collection, doc, setDoc, getDocs, serverTimestamp } from 'https://www.gstatic.com/firebasejs/9.6.1/firebase-firestore.js';
+import { getFirestore, collection, collectionGroup, doc, documentId, setDoc, getDoc, getDocs, onSnapshot, query, where, orderBy, startAt, endAt, writeBatch, serverTimestamp, Timestamp } from 'https://www.gstatic.com/firebasejs/9.6.1/firebase-firestore.js';
 import { getAuth, signInWithPopup, GoogleAuthProvider, signOut, onAuthStateChanged } from 'https://www.gstatic.com/firebasejs/9.6.1/firebase-auth.js';
 
 console.log("Initializing Firebase...");
@@ -66,34 +66,127 @@ export function getCurrentUser() {
     return auth.currentUser;
 }
 
-export async function addFirestoreTaskLog(userId, taskId, logId, units) {
+function opDoc(userId, opId, op) {
+    // The time the server stored it, so that devices can ask for what's new since they last synced.
+    return [doc(db, 'users', userId, 'ops', opId), { ...op, synced_at: serverTimestamp() }];
+}
+
+// Writes the operations, given as `{ id, fields }`, in one batch. If the batch fails they're
+// written one at a time, so that only the ones that can't be written fail. Returns
+// `{ id, error }` for each, with `error` null if it was written.
+export async function addFirestoreOps(userId, ops) {
+    const batch = writeBatch(db);
+    for (const op of ops) {
+        batch.set(...opDoc(userId, op.id, op.fields));
+    }
+
+    try {
+        await batch.commit();
+        return ops.map(op => ({ id: op.id, error: null }));
+    } catch (batchError) {
+        console.error('Batch write failed, retrying one at a time:', batchError);
+    }
+
+    return Promise.all(ops.map(op =>
+        setDoc(...opDoc(userId, op.id, op.fields))
+            .then(() => ({ id: op.id, error: null }))
+            .catch(error => ({ id: op.id, error: String(error) }))
+    ));
+}
+
+// Writes a task the way clients from before the journal stored it. Its document id is the
+// JSON of the task's id, quotes included.
+export async function upsertFirestoreTask(userId, id, task) {
+    const taskRef = doc(db, 'users', userId, 'tasks', id);
+    await setDoc(taskRef, { ...task, updated_at: serverTimestamp() }, { merge: true });
+}
+
+// Writes a log record the way clients from before the journal stored it.
+export async function addFirestoreTaskLog(userId, taskId, logId, log) {
     const taskRef = doc(collection(db, 'users', userId, 'task_logs'), taskId);
     const logRef = doc(collection(taskRef, 'logs'), logId);
-    await setDoc(logRef, {
-        units: units
+    await setDoc(logRef, log);
+}
+
+// Deletes everything stored about a task: its operations, given by id since they don't say which
+// task they're for, and its documents from before there were operations.
+export async function deleteTaskRemotely(userId, taskId, opIds) {
+    const logs = await getDocs(collection(db, 'users', userId, 'task_logs', taskId, 'logs'));
+    let refs = opIds.map(opId => doc(db, 'users', userId, 'ops', opId));
+    logs.forEach(log => refs.push(log.ref));
+    refs.push(doc(db, 'users', userId, 'tasks', JSON.stringify(taskId)));
+
+    // A batch takes at most 500 writes.
+    for (let i = 0; i < refs.length; i += 400) {
+        const batch = writeBatch(db);
+        refs.slice(i, i + 400).forEach(ref => batch.delete(ref));
+        await batch.commit();
+    }
+}
+
+// Every operation if `sinceMillis` is null, otherwise the ones stored after then.
+export async function loadOps(userId, sinceMillis) {
+    let ref = collection(db, 'users', userId, 'ops');
+    if (sinceMillis !== null) {
+        ref = query(ref, where('synced_at', '>', Timestamp.fromMillis(sinceMillis)));
+    }
+
+    const querySnapshot = await getDocs(ref);
+    let ops = [];
+
+    querySnapshot.forEach(doc => {
+        const data = doc.data();
+        ops.push({ ...data, id: doc.id, synced_at: data.synced_at ? data.synced_at.toMillis() : null });
     });
+
+    return ops;
 }
 
+// Calls `onOps` with the operations stored after `sinceMillis`, then with new ones as they're
+// stored. Returns a function that stops listening.
+export function subscribeOps(userId, sinceMillis, onOps) {
+    const opsQuery = query(
+        collection(db, 'users', userId, 'ops'),
+        where('synced_at', '>', Timestamp.fromMillis(sinceMillis)),
+    );
+
+    return onSnapshot(opsQuery, snapshot => {
+        let ops = [];
+        snapshot.docChanges().forEach(change => {
+            if (change.type === 'removed') {
+                return;
+            }
+            const data = change.doc.data();
+            ops.push({ ...data, id: change.doc.id, synced_at: data.synced_at ? data.synced_at.toMillis() : null });
+        });
+
+        if (ops.length > 0) {
+            onOps(ops);
+        }
+    }, error => console.log("listening for operations failed: ", error));
+}
+
+// Every log of every task in one query. The per-task documents the logs live under were never
+// written, so they can't be listed, but a collection group query finds the logs regardless.
 export async function loadAllLogs(userId) {
-    const querySnapshot = await getDocs(collection(db, 'users', userId, 'task_logs'));
+    const prefix = `users/${userId}/task_logs/`;
+    const logsQuery = query(
+        collectionGroup(db, 'logs'),
+        orderBy(documentId()),
+        startAt(prefix),
+        endAt(prefix + '\uf8ff'),
+    );
+    const querySnapshot = await getDocs(logsQuery);
     let logs = [];
-    let promises = [];
 
-    querySnapshot.forEach(doc => {
-        let taskId = doc.id;
-        let subCollectionRef = collection(db, 'users', userId, 'task_logs', taskId, 'logs');
-        promises.push(getDocs(subCollectionRef).then(subQuerySnapshot => {
-            subQuerySnapshot.forEach(subDoc => {
-                logs.push({
-                    task_id: taskId,
-                    timestamp: subDoc.id,
-                    units: subDoc.data().units 
-                });
-            });
-        }));
+    querySnapshot.forEach(subDoc => {
+        logs.push({
+            ...subDoc.data(),
+            task_id: subDoc.ref.parent.parent.id,
+            timestamp: subDoc.id
+        });
     });
 
-    await Promise.all(promises);
     return logs;
 }
 
@@ -105,38 +198,21 @@ export async function loadLogsForTask(userId, taskId) {
 
     querySnapshot.forEach(subDoc => {
         logs.push({
+            ...subDoc.data(),
             task_id: taskId,
-            timestamp: subDoc.id,
-            units: subDoc.data().units 
+            timestamp: subDoc.id
         });
     });
 
     return logs;
 }
 
-export function upsertFirestoreTask(userId, id, task) {
-  return new Promise((resolve, reject) => {
-    if (typeof db === 'undefined') {
-      console.error('Firestore has not been initialized');
-      reject(new Error('Firestore has not been initialized'));
-      return;
-    }
-    console.log(`Firestore is initialized. Adding/Updating task with ID ${id}:`, task);
-    const taskRef = doc(db, 'users', userId, 'tasks', id);
-    setDoc(taskRef, {
-      ...task,
-      userId: userId, 
-      updated_at: serverTimestamp()
-    }, { merge: true })
-      .then(() => {
-        console.log(`Task with ID ${id} added/updated in Firestore successfully`);
-        resolve();
-      })
-      .catch((error) => {
-        console.error(`Error adding/updating task with ID ${id} in Firestore:`, error);
-        reject(error);
-      });
-  });
+// Writes the server's time to a document and reads it back, in milliseconds.
+export async function probeServerTime(userId) {
+    const clockRef = doc(db, 'users', userId, 'meta', 'clock');
+    await setDoc(clockRef, { updated_at: serverTimestamp() });
+    const snapshot = await getDoc(clockRef);
+    return snapshot.data().updated_at.toMillis();
 }
 
 export function loadAllTasks(userId) {
@@ -151,7 +227,8 @@ export function loadAllTasks(userId) {
       .then((querySnapshot) => {
         const tasks = [];
         querySnapshot.forEach((doc) => {
-          tasks.push({ id: doc.id, ...doc.data() });
+          const data = doc.data();
+          tasks.push({ id: doc.id, ...data, updated_at: data.updated_at?.toMillis() });
         });
         console.log('All tasks loaded from Firestore successfully');
         resolve(tasks);
@@ -0,0 +1,234 @@
+use crate::store::{now_millis, Doc, Store, Stored};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+/// A request, with what the handlers need of it.
+pub struct Request<'a> {
+    pub method: &'a str,
+    // The path and query string.
+    pub url: &'a str,
+    // From the `Authorization: Bearer <token>` header.
+    pub token: Option<&'a str>,
+    pub body: &'a str,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Self {
+        Self { status: 200, body }
+    }
+
+    fn error(status: u16, e: impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            body: json!({ "error": e.to_string() }),
+        }
+    }
+}
+
+fn query<'a>(url: &'a str, key: &str) -> Option<&'a str> {
+    let (_, query) = url.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| v)
+}
+
+/// Answers a request, see the protocol in `README.md`.
+pub fn handle(store: &mut Store, req: &Request) -> Response {
+    let path = req.url.split('?').next().unwrap_or_default();
+
+    // The only thing anyone can ask without a token.
+    if (req.method, path) == ("GET", "/time") {
+        return Response::ok(json!({ "millis": now_millis() }));
+    }
+
+    let user = match req.token.map(|token| store.user(token)) {
+        Some(Ok(Some(user))) => user,
+        Some(Err(e)) => return Response::error(500, e),
+        _ => return Response::error(401, "missing or unknown token"),
+    };
+
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (req.method, segments.as_slice()) {
+        ("GET", ["me"]) => Response::ok(json!({ "user": user })),
+        ("GET", ["ops"]) => {
+            let since = match query(req.url, "since").map(str::parse::<u64>) {
+                Some(Ok(since)) => Some(since),
+                Some(Err(e)) => return Response::error(400, format!("bad since: {}", e)),
+                None => None,
+            };
+            match store.load(&user, since) {
+                Ok(docs) => Response::ok(json!(docs)),
+                Err(e) => Response::error(500, e),
+            }
+        }
+        ("POST", ["ops"]) => append(store, &user, req.body),
+        ("DELETE", ["tasks", task]) => {
+            let Ok(task) = task.parse::<Uuid>() else {
+                return Response::error(400, "bad task id");
+            };
+            match store.delete_task(&user, task) {
+                Ok(deleted) => Response::ok(json!({ "deleted": deleted })),
+                Err(e) => Response::error(500, e),
+            }
+        }
+        _ => Response::error(404, "no such endpoint"),
+    }
+}
+
+/// Stores the operations that can be read, and says for each whether it was.
+fn append(store: &mut Store, user: &str, body: &str) -> Response {
+    let docs: Vec<Value> = match serde_json::from_str(body) {
+        Ok(docs) => docs,
+        Err(e) => return Response::error(400, e),
+    };
+
+    let mut results = vec![];
+    let mut valid = vec![];
+    for doc in docs {
+        let id = doc
+            .get("id")
+            .and_then(|id| id.as_str()?.parse::<Uuid>().ok());
+        match (id, serde_json::from_value::<Doc>(doc)) {
+            (Some(_), Ok(doc)) => valid.push(doc),
+            (Some(id), Err(e)) => results.push(Stored {
+                id,
+                error: Some(e.to_string()),
+            }),
+            // Nothing to report it by.
+            (None, _) => return Response::error(400, "operation without an id"),
+        }
+    }
+
+    if let Err(e) = store.append(user, &valid) {
+        return Response::error(500, e);
+    }
+    results.extend(valid.iter().map(|doc| Stored {
+        id: doc.id,
+        error: None,
+    }));
+    Response::ok(json!(results))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(task: Uuid) -> Value {
+        json!({
+            "id": Uuid::new_v4(),
+            "task_id": task,
+            "op": "{}",
+            "v": 1,
+        })
+    }
+
+    fn request<'a>(method: &'a str, url: &'a str, token: &'a str, body: &'a str) -> Request<'a> {
+        Request {
+            method,
+            url,
+            token: Some(token),
+            body,
+        }
+    }
+
+    #[test]
+    fn test_auth() {
+        let mut store = Store::in_memory().unwrap();
+        let token = store.add_token("tor").unwrap();
+
+        let me = handle(&mut store, &request("GET", "/me", &token, ""));
+        assert_eq!(me, Response::ok(json!({ "user": "tor" })));
+
+        let stranger = handle(&mut store, &request("GET", "/ops", "nope", ""));
+        assert_eq!(stranger.status, 401);
+        let anyone = Request {
+            method: "GET",
+            url: "/time",
+            token: None,
+            body: "",
+        };
+        assert_eq!(handle(&mut store, &anyone).status, 200);
+
+        assert!(store.revoke_token(&token).unwrap());
+        let revoked = handle(&mut store, &request("GET", "/me", &token, ""));
+        assert_eq!(revoked.status, 401);
+    }
+
+    #[test]
+    fn test_ops() {
+        let mut store = Store::in_memory().unwrap();
+        let tor = store.add_token("tor").unwrap();
+        let other = store.add_token("other").unwrap();
+        let (dishes, plants) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let mut bad = doc(dishes);
+        bad["v"] = json!("one");
+        let body = json!([doc(dishes), doc(plants), bad]).to_string();
+        let res = handle(&mut store, &request("POST", "/ops", &tor, &body));
+        let results = res.body.as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results.iter().filter(|r| r.get("error").is_some()).count(),
+            1
+        );
+
+        let all = handle(&mut store, &request("GET", "/ops", &tor, ""));
+        let all = all.body.as_array().unwrap();
+        assert_eq!(all.len(), 2);
+        let synced_at = all[0]["synced_at"].as_u64().unwrap();
+
+        // Users only see their own.
+        let theirs = handle(&mut store, &request("GET", "/ops", &other, ""));
+        assert_eq!(theirs.body, json!([]));
+
+        let url = format!("/ops?since={}", synced_at);
+        let since = handle(&mut store, &request("GET", &url, &tor, ""));
+        assert_eq!(since.body, json!([]));
+
+        let url = format!("/tasks/{}", dishes);
+        let deleted = handle(&mut store, &request("DELETE", &url, &tor, ""));
+        assert_eq!(deleted.body, json!({ "deleted": 1 }));
+        let left = handle(&mut store, &request("GET", "/ops", &tor, ""));
+        assert_eq!(left.body[0]["task_id"], json!(plants));
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut store = Store::in_memory().unwrap();
+        let tor = store.add_token("tor").unwrap();
+        let mut op = doc(Uuid::new_v4());
+        let body = json!([op]).to_string();
+        handle(&mut store, &request("POST", "/ops", &tor, &body));
+
+        op["op"] = json!("{\"newer\":true}");
+        let body = json!([op]).to_string();
+        handle(&mut store, &request("POST", "/ops", &tor, &body));
+
+        let all = handle(&mut store, &request("GET", "/ops", &tor, ""));
+        assert_eq!(all.body.as_array().unwrap().len(), 1);
+        assert_eq!(all.body[0]["op"], op["op"]);
+    }
+
+    #[test]
+    fn test_bad_requests() {
+        let mut store = Store::in_memory().unwrap();
+        let tor = store.add_token("tor").unwrap();
+
+        let status = |store: &mut Store, method, url, body| {
+            handle(store, &request(method, url, &tor, body)).status
+        };
+        assert_eq!(status(&mut store, "POST", "/ops", "not json"), 400);
+        assert_eq!(status(&mut store, "POST", "/ops", "[{\"v\": 1}]"), 400);
+        assert_eq!(status(&mut store, "GET", "/ops?since=soon", ""), 400);
+        assert_eq!(status(&mut store, "DELETE", "/tasks/dishes", ""), 400);
+        assert_eq!(status(&mut store, "GET", "/tasks", ""), 404);
+    }
+}
@@ -0,0 +1,166 @@
+//! A sync server to run instead of Firestore. Stores each user's operations in SQLite and
+//! serves them over HTTP, see `README.md` for the protocol.
+
+use std::io::Read;
+use store::Store;
+use tiny_http::{Header, Method, Server};
+
+mod api;
+mod store;
+
+const USAGE: &str = "usage:
+  firelog-server [--db PATH] serve [ADDRESS]   serve on ADDRESS, 127.0.0.1:8787 by default
+  firelog-server [--db PATH] token USER        make a token for USER, creating them
+  firelog-server [--db PATH] revoke TOKEN      stop a token from working
+
+The database is firelog.db in the current directory unless given.";
+
+// Bigger bodies are turned away. A full upload is a few thousand small operations.
+const MAX_BODY: u64 = 32 * 1024 * 1024;
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+/// Lets the web app call the server from wherever it's hosted. Tokens are sent as headers
+/// rather than cookies, so any origin is fine.
+fn cors_headers() -> Vec<Header> {
+    vec![
+        header("Access-Control-Allow-Origin", "*"),
+        header("Access-Control-Allow-Methods", "GET, POST, DELETE, OPTIONS"),
+        header(
+            "Access-Control-Allow-Headers",
+            "Authorization, Content-Type",
+        ),
+    ]
+}
+
+fn listen(store: Store, address: &str) -> Result<(), String> {
+    let server = Server::http(address).map_err(|e| e.to_string())?;
+    println!("listening on http://{}", address);
+    serve(store, &server);
+    Ok(())
+}
+
+fn serve(mut store: Store, server: &Server) {
+    for mut request in server.incoming_requests() {
+        let mut response = if *request.method() == Method::Options {
+            tiny_http::Response::empty(204).boxed()
+        } else {
+            let mut body = String::new();
+            let read = request.as_reader().take(MAX_BODY).read_to_string(&mut body);
+
+            let token = request
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv("Authorization"))
+                .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+                .map(str::to_string);
+
+            let res = match read {
+                Ok(_) => api::handle(
+                    &mut store,
+                    &api::Request {
+                        method: request.method().as_str(),
+                        url: request.url(),
+                        token: token.as_deref(),
+                        body: &body,
+                    },
+                ),
+                Err(e) => api::Response {
+                    status: 400,
+                    body: serde_json::json!({ "error": e.to_string() }),
+                },
+            };
+            if res.status >= 500 {
+                eprintln!("{} {}: {}", request.method(), request.url(), res.body);
+            }
+
+            tiny_http::Response::from_string(res.body.to_string())
+                .with_status_code(res.status)
+                .with_header(header("Content-Type", "application/json"))
+                .boxed()
+        };
+
+        for header in cors_headers() {
+            response.add_header(header);
+        }
+        if let Err(e) = request.respond(response) {
+            eprintln!("failed to respond: {}", e);
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let (db, args) = match args {
+        [flag, path, rest @ ..] if flag == "--db" => (path.as_str(), rest),
+        _ => ("firelog.db", args),
+    };
+    let open = || Store::open(db).map_err(|e| format!("can't open {}: {}", db, e));
+
+    match args {
+        [cmd] if cmd == "serve" => listen(open()?, "127.0.0.1:8787"),
+        [cmd, address] if cmd == "serve" => listen(open()?, address),
+        [cmd, user] if cmd == "token" => {
+            let token = open()?.add_token(user).map_err(|e| e.to_string())?;
+            println!("{}", token);
+            Ok(())
+        }
+        [cmd, token] if cmd == "revoke" => match open()?.revoke_token(token) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err("no such token".to_string()),
+            Err(e) => Err(e.to_string()),
+        },
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    fn send(address: &str, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serve() {
+        let store = Store::in_memory().unwrap();
+        let token = store.add_token("tor").unwrap();
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap().to_string();
+        std::thread::spawn(move || serve(store, &server));
+
+        let me = send(
+            &address,
+            &format!(
+                "GET /me HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {}\r\nConnection: close\r\n\r\n",
+                token
+            ),
+        );
+        assert!(me.starts_with("HTTP/1.1 200"));
+        assert!(me.contains("Access-Control-Allow-Origin: *"));
+        assert!(me.ends_with("{\"user\":\"tor\"}"));
+
+        let preflight = send(
+            &address,
+            "OPTIONS /ops HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        );
+        assert!(preflight.starts_with("HTTP/1.1 204"));
+        assert!(preflight.contains("Access-Control-Allow-Headers: Authorization, Content-Type"));
+    }
+}
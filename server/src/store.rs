@@ -0,0 +1,158 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// An operation as the client sends it. The operation itself is kept as the client wrote it,
+/// so that clients on newer versions of the schema don't lose anything through the server.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Doc {
+    pub id: Uuid,
+    pub task_id: Uuid,
+    pub op: String,
+    pub v: u32,
+    // When the server stored it, in milliseconds since the epoch. Set by the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced_at: Option<u64>,
+}
+
+/// Whether an operation was stored, as returned for each one that's appended.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Stored {
+    pub id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Every user's operations, and the tokens that give access to them.
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> rusqlite::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> rusqlite::Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS tokens (
+                token TEXT PRIMARY KEY,
+                user TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS ops (
+                user TEXT NOT NULL,
+                id TEXT NOT NULL,
+                task_id TEXT NOT NULL,
+                op TEXT NOT NULL,
+                v INTEGER NOT NULL,
+                synced_at INTEGER NOT NULL,
+                PRIMARY KEY (user, id)
+            );
+            CREATE INDEX IF NOT EXISTS ops_by_time ON ops (user, synced_at);",
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// Makes a new token for the user, who exists from then on.
+    pub fn add_token(&self, user: &str) -> rusqlite::Result<String> {
+        let token = Uuid::new_v4().simple().to_string();
+        self.conn.execute(
+            "INSERT INTO tokens (token, user) VALUES (?1, ?2)",
+            params![token, user],
+        )?;
+        Ok(token)
+    }
+
+    /// Returns whether there was such a token.
+    pub fn revoke_token(&self, token: &str) -> rusqlite::Result<bool> {
+        let n = self
+            .conn
+            .execute("DELETE FROM tokens WHERE token = ?1", params![token])?;
+        Ok(n > 0)
+    }
+
+    /// Who the token belongs to.
+    pub fn user(&self, token: &str) -> rusqlite::Result<Option<String>> {
+        self.conn
+            .query_row(
+                "SELECT user FROM tokens WHERE token = ?1",
+                params![token],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Stores the operations, replacing any with the same id.
+    pub fn append(&mut self, user: &str, docs: &[Doc]) -> rusqlite::Result<()> {
+        let synced_at = now_millis();
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO ops (user, id, task_id, op, v, synced_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ON CONFLICT (user, id) DO UPDATE SET
+                    task_id = excluded.task_id,
+                    op = excluded.op,
+                    v = excluded.v,
+                    synced_at = excluded.synced_at",
+            )?;
+            for doc in docs {
+                insert.execute(params![
+                    user,
+                    doc.id.to_string(),
+                    doc.task_id.to_string(),
+                    doc.op,
+                    doc.v,
+                    synced_at as i64
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Every operation of the user's, or the ones stored after `since`, oldest first.
+    pub fn load(&self, user: &str, since: Option<u64>) -> rusqlite::Result<Vec<Doc>> {
+        let mut query = self.conn.prepare(
+            "SELECT id, task_id, op, v, synced_at FROM ops
+            WHERE user = ?1 AND synced_at > ?2
+            ORDER BY synced_at",
+        )?;
+        let since = since.map_or(-1, |since| since as i64);
+        let rows = query.query_map(params![user, since], |row| {
+            let id: String = row.get(0)?;
+            let task_id: String = row.get(1)?;
+            let synced_at: i64 = row.get(4)?;
+            Ok(Doc {
+                // Only ever written from parsed ids.
+                id: id.parse().unwrap_or_default(),
+                task_id: task_id.parse().unwrap_or_default(),
+                op: row.get(2)?,
+                v: row.get(3)?,
+                synced_at: Some(synced_at as u64),
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Deletes the task's operations. Returns how many there were.
+    pub fn delete_task(&self, user: &str, task: Uuid) -> rusqlite::Result<usize> {
+        self.conn.execute(
+            "DELETE FROM ops WHERE user = ?1 AND task_id = ?2",
+            params![user, task.to_string()],
+        )
+    }
+}
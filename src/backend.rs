@@ -0,0 +1,538 @@
+use crate::firebase;
+use crate::journal::Op;
+use crate::schema;
+use crate::task::{LogRecord, MetaData, Task, TaskID, TaskLog};
+use crate::{log, AuthUser};
+use gloo::net::http::{Request, RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+use wasm_bindgen::JsValue;
+
+type UnixTime = Duration;
+
+// Firestore takes at most 500 writes in a batch.
+const UPLOAD_BATCH: usize = 400;
+
+/// Stops listening for operations once dropped.
+pub struct Subscription(#[allow(dead_code)] Box<dyn std::any::Any>);
+
+/// What some operations changed about a task, in the shape clients from before the journal
+/// store it: its metadata if that changed, and the records that were logged or edited. Deleted
+/// records aren't included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegacyChange {
+    pub task: TaskID,
+    pub metadata: Option<MetaData>,
+    pub records: Vec<LogRecord>,
+}
+
+/// Where a user's tasks are synced to. Tasks and their logs are both stored as operations, so
+/// listing and appending operations is all the sync needs, apart from the tasks stored before
+/// there were operations.
+pub trait SyncBackend {
+    /// Whose data this is.
+    fn uid(&self) -> &str;
+
+    /// The server's clock, to tell how far off ours is.
+    async fn server_time(&self) -> Result<UnixTime, String>;
+
+    /// Every operation, or the ones stored after `since`. Also returns when the newest of them
+    /// was stored.
+    async fn load_ops(
+        &self,
+        since: Option<UnixTime>,
+    ) -> Result<(Vec<Op>, Option<UnixTime>), String>;
+
+    /// Stores the operations. Returns the ones that couldn't be stored, with why.
+    async fn append_ops(&self, ops: &[Op]) -> Vec<(Uuid, String)>;
+
+    /// Deletes everything stored about a task, given the ids of its operations.
+    async fn delete_task(&self, task: TaskID, ops: &[Uuid]) -> Result<(), String>;
+
+    /// Tasks and their logs as stored before there were operations.
+    async fn load_legacy(&self) -> Result<HashMap<TaskID, Task>, String>;
+
+    /// Writes the changes the way tasks were stored before there were operations, so that
+    /// clients that haven't been updated yet keep seeing them.
+    async fn save_legacy(&self, changes: &[LegacyChange]) -> Result<(), String>;
+
+    /// Calls `on_ops` with operations as they're stored, from `since` on. None if the backend
+    /// can't tell us.
+    fn subscribe(&self, since: UnixTime, on_ops: Box<dyn FnMut(Vec<Op>)>) -> Option<Subscription>;
+}
+
+pub fn js_error(e: JsValue) -> String {
+    e.as_string()
+        .or_else(|| {
+            js_sys::Reflect::get(&e, &"message".into())
+                .ok()?
+                .as_string()
+        })
+        .unwrap_or_else(|| format!("{:?}", e))
+}
+
+/// The user's data in Firestore, through `assets/firestore.js`.
+pub struct Firestore {
+    pub user: AuthUser,
+}
+
+/// The operations a server said it couldn't store, from `[{"id": .., "error": ..}, ..]`.
+fn failures(results: &[Value]) -> Vec<(Uuid, String)> {
+    results
+        .iter()
+        .filter_map(|res| {
+            let e = res.get("error")?.as_str()?;
+            let id = res.get("id")?.as_str()?.parse().ok()?;
+            Some((id, e.to_string()))
+        })
+        .collect()
+}
+
+impl SyncBackend for Firestore {
+    fn uid(&self) -> &str {
+        &self.user.uid
+    }
+
+    async fn server_time(&self) -> Result<UnixTime, String> {
+        let time = firebase::probe_server_time(&self.user)
+            .await
+            .map_err(js_error)?;
+        let millis = time.as_f64().ok_or("server time isn't a number")?;
+        Ok(UnixTime::from_millis(millis as u64))
+    }
+
+    async fn load_ops(
+        &self,
+        since: Option<UnixTime>,
+    ) -> Result<(Vec<Op>, Option<UnixTime>), String> {
+        let ops = firebase::load_ops(&self.user, since)
+            .await
+            .map_err(js_error)?;
+        Ok(Op::from_jsvalue(ops))
+    }
+
+    /// Writes in batches, see `addFirestoreOps`.
+    async fn append_ops(&self, ops: &[Op]) -> Vec<(Uuid, String)> {
+        let batches: Vec<_> = ops
+            .chunks(UPLOAD_BATCH)
+            .map(|batch| async move {
+                match firebase::add_ops_to_firestore(&self.user.uid, batch).await {
+                    Ok(results) => failures(
+                        &serde_wasm_bindgen::from_value::<Vec<Value>>(results).unwrap_or_default(),
+                    ),
+                    // The whole batch failed before any of it could be tried.
+                    Err(e) => {
+                        let e = js_error(e);
+                        batch.iter().map(|op| (op.id, e.clone())).collect()
+                    }
+                }
+            })
+            .collect();
+
+        futures::future::join_all(batches)
+            .await
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    async fn delete_task(&self, task: TaskID, ops: &[Uuid]) -> Result<(), String> {
+        firebase::delete_task_remotely(&self.user, task, ops)
+            .await
+            .map(|_| ())
+            .map_err(js_error)
+    }
+
+    async fn load_legacy(&self) -> Result<HashMap<TaskID, Task>, String> {
+        let tasks = firebase::load_all_tasks(&self.user)
+            .await
+            .map_err(js_error)?;
+        let (online_tasks, _) = MetaData::from_jsvalue(tasks);
+
+        let mut docs: HashMap<Uuid, Vec<serde_json::Value>> = HashMap::default();
+        match firebase::load_all_logs(&self.user).await {
+            Ok(logs) => {
+                let logs: Vec<serde_json::Value> =
+                    serde_wasm_bindgen::from_value(logs).map_err(|e| e.to_string())?;
+                for doc in logs {
+                    let task_id = doc.get("task_id").and_then(|id| id.as_str()?.parse().ok());
+                    if let Some(task_id) = task_id {
+                        docs.entry(task_id).or_default().push(doc);
+                    }
+                }
+            }
+            // Collection group queries need their own security rule, so fall back to asking for
+            // each task's logs.
+            Err(e) => {
+                log(("loading all logs at once failed: ", e));
+                let futs = online_tasks
+                    .keys()
+                    .map(|id| firebase::load_logs_for_task(self.user.uid.clone(), *id));
+                let results = futures::future::join_all(futs).await;
+                for (id, logs) in online_tasks.keys().zip(results) {
+                    let logs: Vec<serde_json::Value> =
+                        serde_wasm_bindgen::from_value(logs.map_err(js_error)?)
+                            .map_err(|e| e.to_string())?;
+                    docs.insert(*id, logs);
+                }
+            }
+        }
+
+        let mut tasks = HashMap::default();
+        for (id, metadata) in online_tasks {
+            let log = docs
+                .get(&id)
+                .map(|docs| TaskLog::from_docs(docs))
+                .unwrap_or_default();
+            tasks.insert(id, Task { id, log, metadata });
+        }
+        Ok(tasks)
+    }
+
+    /// Only for the transition, until every device reads operations. Stop writing these then,
+    /// and `load_legacy` only has to import what's left from before.
+    async fn save_legacy(&self, changes: &[LegacyChange]) -> Result<(), String> {
+        let uid = &self.user.uid;
+        let tasks = changes.iter().filter_map(|change| {
+            let metadata = change.metadata.as_ref()?;
+            Some(firebase::send_task_to_firestore(uid, change.task, metadata))
+        });
+        let logs = changes.iter().flat_map(|change| {
+            change
+                .records
+                .iter()
+                .map(|record| firebase::add_task_log_to_firestore(uid, change.task, record))
+        });
+
+        futures::future::join_all(tasks.chain(logs))
+            .await
+            .into_iter()
+            .find_map(Result::err)
+            .map_or(Ok(()), |e| Err(js_error(e)))
+    }
+
+    fn subscribe(
+        &self,
+        since: UnixTime,
+        mut on_ops: Box<dyn FnMut(Vec<Op>)>,
+    ) -> Option<Subscription> {
+        let subscription = firebase::subscribe_ops(&self.user, since, move |ops| {
+            let (ops, _) = Op::from_jsvalue(ops);
+            on_ops(ops);
+        });
+        Some(Subscription(Box::new(subscription)))
+    }
+}
+
+/// How to reach a sync server of our own, see `server/README.md`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ServerConfig {
+    pub url: String,
+    pub token: String,
+    // Who the token belongs to, as the server said when connecting.
+    pub user: String,
+}
+
+/// The user's data on a sync server of our own.
+pub struct SelfHosted {
+    config: ServerConfig,
+    // Keeps what's cached about each server, and about Firestore users, apart.
+    uid: String,
+}
+
+/// The body of a successful response, or what went wrong.
+async fn answer(res: Result<Response, gloo::net::Error>) -> Result<Value, String> {
+    let res = res.map_err(|e| e.to_string())?;
+    let body: Value = res.json().await.map_err(|e| e.to_string())?;
+    if res.ok() {
+        return Ok(body);
+    }
+
+    let e = body
+        .get("error")
+        .and_then(Value::as_str)
+        .unwrap_or("no reason given");
+    Err(format!("server answered {}: {}", res.status(), e))
+}
+
+impl SelfHosted {
+    pub fn new(config: ServerConfig) -> Self {
+        let uid = format!("{}@{}", config.user, config.url);
+        Self { config, uid }
+    }
+
+    /// Asks the server who the token belongs to, so that a wrong url or token is caught before
+    /// it's saved.
+    pub async fn connect(url: &str, token: &str) -> Result<ServerConfig, String> {
+        let mut config = ServerConfig {
+            url: url.trim().trim_end_matches('/').to_string(),
+            token: token.trim().to_string(),
+            user: String::new(),
+        };
+        let me = Self::new(config.clone());
+        let res = answer(me.request(Request::get(&me.url("/me"))).send().await).await?;
+        config.user = res
+            .get("user")
+            .and_then(Value::as_str)
+            .ok_or("server didn't say who the token is for")?
+            .to_string();
+        Ok(config)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.config.url, path)
+    }
+
+    fn request(&self, builder: RequestBuilder) -> RequestBuilder {
+        builder.header("Authorization", &format!("Bearer {}", self.config.token))
+    }
+}
+
+impl SyncBackend for SelfHosted {
+    fn uid(&self) -> &str {
+        &self.uid
+    }
+
+    async fn server_time(&self) -> Result<UnixTime, String> {
+        let res = answer(Request::get(&self.url("/time")).send().await).await?;
+        let millis = res
+            .get("millis")
+            .and_then(Value::as_u64)
+            .ok_or("server time isn't a number")?;
+        Ok(UnixTime::from_millis(millis))
+    }
+
+    async fn load_ops(
+        &self,
+        since: Option<UnixTime>,
+    ) -> Result<(Vec<Op>, Option<UnixTime>), String> {
+        let url = match since {
+            Some(since) => self.url(&format!("/ops?since={}", since.as_millis())),
+            None => self.url("/ops"),
+        };
+        let res = answer(self.request(Request::get(&url)).send().await).await?;
+        let docs = res.as_array().ok_or("operations aren't a list")?;
+        Ok(Op::from_docs(docs))
+    }
+
+    async fn append_ops(&self, ops: &[Op]) -> Vec<(Uuid, String)> {
+        let docs: Vec<Value> = ops
+            .iter()
+            .map(|op| {
+                json!({
+                    "id": op.id,
+                    "task_id": op.task,
+                    "op": serde_json::to_string(op).unwrap(),
+                    "v": schema::OP.version(),
+                })
+            })
+            .collect();
+
+        let res = match self.request(Request::post(&self.url("/ops"))).json(&docs) {
+            Ok(request) => answer(request.send().await).await,
+            Err(e) => Err(e.to_string()),
+        };
+        match res {
+            Ok(results) => failures(results.as_array().map(Vec::as_slice).unwrap_or_default()),
+            Err(e) => ops.iter().map(|op| (op.id, e.clone())).collect(),
+        }
+    }
+
+    async fn delete_task(&self, task: TaskID, _ops: &[Uuid]) -> Result<(), String> {
+        let url = self.url(&format!("/tasks/{}", task));
+        answer(self.request(Request::delete(&url)).send().await)
+            .await
+            .map(|_| ())
+    }
+
+    /// The server came after the journal, so there's nothing from before it.
+    async fn load_legacy(&self) -> Result<HashMap<TaskID, Task>, String> {
+        Ok(HashMap::default())
+    }
+
+    /// Nor any clients that only read the old format.
+    async fn save_legacy(&self, _changes: &[LegacyChange]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// The server doesn't push, its operations come in with each sync.
+    fn subscribe(
+        &self,
+        _since: UnixTime,
+        _on_ops: Box<dyn FnMut(Vec<Op>)>,
+    ) -> Option<Subscription> {
+        None
+    }
+}
+
+/// Whichever backend the user syncs with.
+pub enum Remote {
+    Firestore(Firestore),
+    SelfHosted(SelfHosted),
+}
+
+impl SyncBackend for Remote {
+    fn uid(&self) -> &str {
+        match self {
+            Self::Firestore(backend) => backend.uid(),
+            Self::SelfHosted(backend) => backend.uid(),
+        }
+    }
+
+    async fn server_time(&self) -> Result<UnixTime, String> {
+        match self {
+            Self::Firestore(backend) => backend.server_time().await,
+            Self::SelfHosted(backend) => backend.server_time().await,
+        }
+    }
+
+    async fn load_ops(
+        &self,
+        since: Option<UnixTime>,
+    ) -> Result<(Vec<Op>, Option<UnixTime>), String> {
+        match self {
+            Self::Firestore(backend) => backend.load_ops(since).await,
+            Self::SelfHosted(backend) => backend.load_ops(since).await,
+        }
+    }
+
+    async fn append_ops(&self, ops: &[Op]) -> Vec<(Uuid, String)> {
+        match self {
+            Self::Firestore(backend) => backend.append_ops(ops).await,
+            Self::SelfHosted(backend) => backend.append_ops(ops).await,
+        }
+    }
+
+    async fn delete_task(&self, task: TaskID, ops: &[Uuid]) -> Result<(), String> {
+        match self {
+            Self::Firestore(backend) => backend.delete_task(task, ops).await,
+            Self::SelfHosted(backend) => backend.delete_task(task, ops).await,
+        }
+    }
+
+    async fn load_legacy(&self) -> Result<HashMap<TaskID, Task>, String> {
+        match self {
+            Self::Firestore(backend) => backend.load_legacy().await,
+            Self::SelfHosted(backend) => backend.load_legacy().await,
+        }
+    }
+
+    async fn save_legacy(&self, changes: &[LegacyChange]) -> Result<(), String> {
+        match self {
+            Self::Firestore(backend) => backend.save_legacy(changes).await,
+            Self::SelfHosted(backend) => backend.save_legacy(changes).await,
+        }
+    }
+
+    fn subscribe(&self, since: UnixTime, on_ops: Box<dyn FnMut(Vec<Op>)>) -> Option<Subscription> {
+        match self {
+            Self::Firestore(backend) => backend.subscribe(since, on_ops),
+            Self::SelfHosted(backend) => backend.subscribe(since, on_ops),
+        }
+    }
+}
+
+/// A server in memory, for testing the sync without one.
+#[cfg(test)]
+#[derive(Default)]
+pub struct Memory {
+    // The operations, with when they were stored.
+    pub ops: std::cell::RefCell<Vec<(Op, UnixTime)>>,
+    pub legacy: std::cell::RefCell<HashMap<TaskID, Task>>,
+    // Operations that fail to be stored.
+    pub failing: std::cell::RefCell<std::collections::HashSet<Uuid>>,
+    now: std::cell::Cell<UnixTime>,
+}
+
+#[cfg(test)]
+impl Memory {
+    /// Every write is stored a while after the last, so each is newer than the cursor overlap.
+    fn store_time(&self) -> UnixTime {
+        self.now.set(self.now.get() + Duration::from_secs(1000));
+        self.now.get()
+    }
+
+    pub fn stored(&self, id: Uuid) -> bool {
+        self.ops.borrow().iter().any(|(op, _)| op.id == id)
+    }
+}
+
+#[cfg(test)]
+impl SyncBackend for Memory {
+    fn uid(&self) -> &str {
+        "user"
+    }
+
+    async fn server_time(&self) -> Result<UnixTime, String> {
+        Ok(self.now.get())
+    }
+
+    async fn load_ops(
+        &self,
+        since: Option<UnixTime>,
+    ) -> Result<(Vec<Op>, Option<UnixTime>), String> {
+        let ops = self.ops.borrow();
+        let new: Vec<&(Op, UnixTime)> = ops
+            .iter()
+            .filter(|(_, stored)| since.is_none_or(|since| *stored > since))
+            .collect();
+        let newest = new.iter().map(|(_, stored)| *stored).max();
+        Ok((new.into_iter().map(|(op, _)| op.clone()).collect(), newest))
+    }
+
+    async fn append_ops(&self, ops: &[Op]) -> Vec<(Uuid, String)> {
+        let stored = self.store_time();
+        let mut failed = vec![];
+        for op in ops {
+            if self.failing.borrow().contains(&op.id) {
+                failed.push((op.id, "refused".to_string()));
+                continue;
+            }
+            let mut all = self.ops.borrow_mut();
+            all.retain(|(old, _)| old.id != op.id);
+            all.push((op.clone(), stored));
+        }
+        failed
+    }
+
+    async fn delete_task(&self, task: TaskID, _ops: &[Uuid]) -> Result<(), String> {
+        self.ops.borrow_mut().retain(|(op, _)| op.task != task);
+        Ok(())
+    }
+
+    async fn load_legacy(&self) -> Result<HashMap<TaskID, Task>, String> {
+        Ok(self.legacy.borrow().clone())
+    }
+
+    async fn save_legacy(&self, changes: &[LegacyChange]) -> Result<(), String> {
+        let mut legacy = self.legacy.borrow_mut();
+        for change in changes {
+            if let Some(metadata) = &change.metadata {
+                let task = legacy.entry(change.task).or_insert_with(|| Task {
+                    id: change.task,
+                    log: TaskLog::default(),
+                    metadata: metadata.clone(),
+                });
+                task.metadata = metadata.clone();
+            }
+            // Clients from before the journal don't show logs of tasks they don't have.
+            if let Some(task) = legacy.get_mut(&change.task) {
+                for record in &change.records {
+                    task.log.upsert(record.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn subscribe(
+        &self,
+        _since: UnixTime,
+        _on_ops: Box<dyn FnMut(Vec<Op>)>,
+    ) -> Option<Subscription> {
+        None
+    }
+}
@@ -0,0 +1,266 @@
+use crate::journal::Journal;
+use crate::task::{Task, TaskID};
+use crate::{cache, log, utils};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+type UnixTime = Duration;
+
+const DAY: u64 = 86400;
+// One backup a day is kept for this many days, then one a week until they're this old.
+const DAILY_DAYS: u64 = 7;
+const WEEKLY_DAYS: u64 = 63;
+// Local storage is only a few megabytes, and the journal itself needs most of it.
+const MAX_BYTES: usize = 2_000_000;
+
+/// A snapshot of the journal. The snapshot itself is stored separately, since it's big.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BackupInfo {
+    pub taken: UnixTime,
+    pub tasks: usize,
+    pub logs: usize,
+    pub bytes: usize,
+}
+
+fn day(time: UnixTime) -> u64 {
+    time.as_secs() / DAY
+}
+
+/// Whether there's no backup from today yet.
+fn due(backups: &[BackupInfo], now: UnixTime) -> bool {
+    !backups.iter().any(|backup| day(backup.taken) == day(now))
+}
+
+/// The backups to keep: the newest of each day for the last week, then the newest of each
+/// week, as long as they fit.
+fn retained(mut backups: Vec<BackupInfo>, now: UnixTime) -> Vec<BackupInfo> {
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.taken));
+
+    let mut buckets = vec![];
+    let mut bytes = 0;
+    let mut kept = vec![];
+
+    for backup in backups {
+        let age = day(now).saturating_sub(day(backup.taken));
+        let bucket = if age < DAILY_DAYS {
+            (0, day(backup.taken))
+        } else if age < WEEKLY_DAYS {
+            (1, day(backup.taken) / 7)
+        } else {
+            continue;
+        };
+
+        if buckets.contains(&bucket) {
+            continue;
+        }
+
+        bytes += backup.bytes;
+        if bytes > MAX_BYTES && !kept.is_empty() {
+            break;
+        }
+
+        buckets.push(bucket);
+        kept.push(backup);
+    }
+
+    kept
+}
+
+/// Takes today's backup if it hasn't been taken, and prunes the old ones.
+pub fn run(journal: &Journal) {
+    if journal.ops().is_empty() {
+        return;
+    }
+
+    let now = utils::current_time();
+    let mut backups = cache::backup_index();
+    if !due(&backups, now) {
+        return;
+    }
+
+    let tasks = journal.replay();
+    let json = journal.to_json();
+    if !cache::save_backup(now, &json) {
+        log("no room for a backup");
+        return;
+    }
+
+    backups.push(BackupInfo {
+        taken: now,
+        tasks: tasks.len(),
+        logs: tasks.values().map(|task| task.log.records().count()).sum(),
+        bytes: json.len(),
+    });
+
+    let kept = retained(backups.clone(), now);
+    for backup in backups {
+        if !kept.contains(&backup) {
+            cache::remove_backup(backup.taken);
+        }
+    }
+    cache::save_backup_index(&kept);
+}
+
+/// What restoring `backup` would change about `current`, one line per task.
+pub fn diff(backup: &HashMap<TaskID, Task>, current: &HashMap<TaskID, Task>) -> Vec<String> {
+    let mut lines = vec![];
+
+    for (id, task) in current {
+        let name = &task.metadata.name;
+        let Some(old) = backup.get(id) else {
+            if !task.metadata.deleted {
+                lines.push(format!("{}: added since, would be deleted", name));
+            }
+            continue;
+        };
+
+        let mut changes = vec![];
+        if old.metadata.name != task.metadata.name {
+            changes.push(format!("renamed from {}", old.metadata.name));
+        }
+        if old.metadata.value != task.metadata.value || old.metadata.length != task.metadata.length
+        {
+            changes.push("edited".to_string());
+        }
+        if old.metadata.deleted != task.metadata.deleted {
+            changes.push(
+                if task.metadata.deleted {
+                    "deleted"
+                } else {
+                    "restored"
+                }
+                .to_string(),
+            );
+        }
+
+        let added = task
+            .log
+            .records()
+            .filter(|rec| old.log.get(rec.id).is_none())
+            .count();
+        let removed = old
+            .log
+            .records()
+            .filter(|rec| task.log.get(rec.id).is_none())
+            .count();
+        if added > 0 {
+            changes.push(format!("{} logs added", added));
+        }
+        if removed > 0 {
+            changes.push(format!("{} logs removed", removed));
+        }
+
+        if !changes.is_empty() {
+            lines.push(format!("{}: {} since", name, changes.join(", ")));
+        }
+    }
+
+    for (id, old) in backup {
+        if !current.contains_key(id) {
+            lines.push(format!(
+                "{}: missing, would be brought back",
+                old.metadata.name
+            ));
+        }
+    }
+
+    lines.sort();
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::{LogRecord, MetaData, TaskLog};
+    use uuid::Uuid;
+
+    fn backup(days: u64, bytes: usize) -> BackupInfo {
+        BackupInfo {
+            taken: UnixTime::from_secs(days * DAY + 3600),
+            tasks: 1,
+            logs: 1,
+            bytes,
+        }
+    }
+
+    #[test]
+    fn test_retention() {
+        let now = UnixTime::from_secs(100 * DAY + 7200);
+        assert!(!due(&[backup(100, 10)], now));
+        assert!(due(&[backup(99, 10)], now));
+
+        // A backup a day for the last hundred days, and a second one today.
+        let mut backups: Vec<BackupInfo> = (1..=100).map(|days| backup(days, 10)).collect();
+        let mut earlier = backup(100, 10);
+        earlier.taken -= UnixTime::from_secs(60);
+        backups.push(earlier);
+
+        let kept = retained(backups, now);
+        let days: Vec<u64> = kept.iter().map(|backup| day(backup.taken)).collect();
+        assert_eq!(&days[..7], &[100, 99, 98, 97, 96, 95, 94]);
+        assert_eq!(kept[0].taken, backup(100, 10).taken);
+
+        // Then one a week, and nothing older than nine weeks.
+        let weeks: Vec<u64> = days[7..].iter().map(|day| day / 7).collect();
+        let mut deduped = weeks.clone();
+        deduped.dedup();
+        assert_eq!(weeks, deduped);
+        assert!(days.iter().all(|day| 100 - day < WEEKLY_DAYS));
+    }
+
+    #[test]
+    fn test_size_cap() {
+        let now = UnixTime::from_secs(100 * DAY);
+        let backups = vec![backup(100, MAX_BYTES * 2), backup(99, 10)];
+        assert_eq!(retained(backups, now).len(), 1);
+
+        let backups = vec![
+            backup(100, MAX_BYTES / 2),
+            backup(99, MAX_BYTES / 2 + 1),
+            backup(98, 1),
+        ];
+        assert_eq!(retained(backups, now).len(), 1);
+    }
+
+    fn task(name: &str, logs: &[LogRecord]) -> Task {
+        let version = crate::task::Version::new(UnixTime::from_secs(DAY).into(), Uuid::nil());
+        Task {
+            id: Uuid::new_v4(),
+            log: TaskLog::from(logs.to_vec()),
+            metadata: MetaData::test(name, version),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let record = |secs| LogRecord {
+            id: Uuid::new_v4(),
+            time: UnixTime::from_secs(secs),
+            units: 1.,
+            ..Default::default()
+        };
+        let (first, second) = (record(100), record(200));
+
+        let dishes = task("dishes", std::slice::from_ref(&first));
+        let plates = task("plates", &[]);
+        let backup: HashMap<TaskID, Task> =
+            [(dishes.id, dishes.clone()), (plates.id, plates.clone())].into();
+
+        let mut renamed = dishes.clone();
+        renamed.metadata.name = "do the dishes".to_string();
+        renamed.log = TaskLog::from(vec![second]);
+        let cups = task("cups", &[]);
+        let current: HashMap<TaskID, Task> = [(renamed.id, renamed), (cups.id, cups)].into();
+
+        assert_eq!(
+            diff(&backup, &current),
+            [
+                "cups: added since, would be deleted",
+                "do the dishes: renamed from dishes, 1 logs added, 1 logs removed since",
+                "plates: missing, would be brought back",
+            ]
+        );
+        assert!(diff(&backup, &backup).is_empty());
+    }
+}
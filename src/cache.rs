@@ -1,9 +1,25 @@
+use crate::backend::ServerConfig;
+use crate::backup::BackupInfo;
+use crate::clock::Hlc;
+use crate::conflict::Conflict;
+use crate::journal::Journal;
+use crate::schema::Unreadable;
+use crate::sync::SyncCursor;
 use crate::task::{Task, TaskLog};
-use crate::{log, log_to_console, MetaData};
-use std::collections::HashMap;
+use crate::{log, log_to_console, utils, MetaData};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use uuid::Uuid;
 use web_sys::{window, Storage};
 
+const QUARANTINE_PREFIX: &str = "quarantine_";
+
+thread_local! {
+    // Keys whose stored value is known to be readable, or has been quarantined.
+    static CHECKED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
 pub fn save_uid(uid: &str) {
     save("uid", uid);
 }
@@ -12,9 +28,283 @@ pub async fn load_uid() -> Option<String> {
     load("uid").await
 }
 
-pub fn save_logs(logs: HashMap<Uuid, TaskLog>) {
-    let s = serde_json::to_string(&logs).unwrap();
-    save("logs", &s);
+/// Identifies this device in the versions of the changes it makes. Created on first use.
+pub fn device_id() -> Uuid {
+    if let Some(id) = storage()
+        .get_item("device_id")
+        .ok()
+        .flatten()
+        .and_then(|id| id.parse().ok())
+    {
+        return id;
+    }
+
+    let id = Uuid::new_v4();
+    guard("device_id", |s| s.parse::<Uuid>().is_ok());
+    save("device_id", &id.to_string());
+    id
+}
+
+pub fn save_clock(clock: Hlc) {
+    guard("clock", |s| serde_json::from_str::<Hlc>(s).is_ok());
+    save("clock", &serde_json::to_string(&clock).unwrap());
+}
+
+pub fn load_clock() -> Option<Hlc> {
+    let s = storage().get_item("clock").ok().flatten()?;
+    serde_json::from_str(&s).ok()
+}
+
+pub fn save_tick_rate(secs: &str) {
+    save("tick_rate", secs);
+}
+
+pub async fn load_tick_rate() -> Option<String> {
+    load("tick_rate").await
+}
+
+pub fn save_journal(journal: &Journal) {
+    guard("journal", |s| Journal::from_json(s).is_ok());
+    save("journal", &journal.to_json());
+}
+
+/// None if there's no journal yet, i.e. the tasks haven't been migrated to it. A journal that
+/// can't be read is quarantined, and we start over with an empty one.
+pub async fn fetch_journal() -> Option<Journal> {
+    let s = load("journal").await?;
+    checked("journal");
+
+    match Journal::from_json(&s) {
+        Ok(journal) => {
+            if journal.unreadable() > 0 {
+                log(("keeping unreadable operations: ", journal.unreadable()));
+            }
+            Some(journal)
+        }
+        Err(Unreadable::Newer(version)) => {
+            // Set aside rather than overwritten, and the operations come back with the next sync.
+            log_to_console(format!("journal is from a newer version ({})", version));
+            save(&format!("journal_v{}", version), &s);
+            Some(Journal::default())
+        }
+        Err(e) => {
+            log_to_console(format!("Deserialization error: {}", e));
+            quarantine("journal", &s);
+            Some(Journal::default())
+        }
+    }
+}
+
+pub fn backup_index() -> Vec<BackupInfo> {
+    storage()
+        .get_item("backups")
+        .ok()
+        .flatten()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_backup_index(backups: &[BackupInfo]) {
+    guard("backups", |s| {
+        serde_json::from_str::<Vec<BackupInfo>>(s).is_ok()
+    });
+    save("backups", &serde_json::to_string(backups).unwrap());
+}
+
+fn backup_key(taken: Duration) -> String {
+    format!("backup_{}", taken.as_millis())
+}
+
+/// Returns false if there wasn't room for it.
+pub fn save_backup(taken: Duration, json: &str) -> bool {
+    storage().set_item(&backup_key(taken), json).is_ok()
+}
+
+pub fn load_backup(taken: Duration) -> Option<String> {
+    storage().get_item(&backup_key(taken)).ok().flatten()
+}
+
+pub fn remove_backup(taken: Duration) {
+    let _ = storage().remove_item(&backup_key(taken));
+}
+
+/// Data that couldn't be read, as it was stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quarantined {
+    // The storage key it's been moved to.
+    pub key: String,
+    // Where it was stored.
+    pub source: String,
+    pub raw: String,
+}
+
+/// Copies unreadable data to a key of its own, where nothing writes.
+pub fn quarantine(key: &str, raw: &str) {
+    let backup = format!(
+        "{}{}_{}",
+        QUARANTINE_PREFIX,
+        key,
+        utils::current_time().as_millis()
+    );
+    log(("quarantining unreadable data: ", key, &backup));
+    save(&backup, raw);
+}
+
+pub fn quarantined() -> Vec<Quarantined> {
+    let storage = storage();
+    let len = storage.length().unwrap_or_default();
+    let mut quarantined = vec![];
+
+    for idx in 0..len {
+        let Some(key) = storage.key(idx).ok().flatten() else {
+            continue;
+        };
+        let Some(rest) = key.strip_prefix(QUARANTINE_PREFIX) else {
+            continue;
+        };
+        let source = rest.rsplit_once('_').map_or(rest, |(source, _)| source);
+        if let Some(raw) = storage.get_item(&key).ok().flatten() {
+            quarantined.push(Quarantined {
+                source: source.to_string(),
+                key,
+                raw,
+            });
+        }
+    }
+
+    quarantined.sort_by(|a, b| a.key.cmp(&b.key));
+    quarantined
+}
+
+pub fn discard_quarantined(key: &str) {
+    if key.starts_with(QUARANTINE_PREFIX) {
+        let _ = storage().remove_item(key);
+    }
+}
+
+fn checked(key: &str) {
+    CHECKED.with(|checked| checked.borrow_mut().insert(key.to_string()));
+}
+
+/// Quarantines what's stored under `key` if it can't be read, before it's written over. Only
+/// checks once per key.
+fn guard(key: &str, readable: impl Fn(&str) -> bool) {
+    if CHECKED.with(|checked| checked.borrow().contains(key)) {
+        return;
+    }
+
+    if let Some(raw) = storage().get_item(key).ok().flatten() {
+        if !readable(&raw) {
+            quarantine(key, &raw);
+        }
+    }
+    checked(key);
+}
+
+pub fn sync_cursor(uid: &str) -> Option<SyncCursor> {
+    let s = storage()
+        .get_item(&format!("sync_cursor_{}", uid))
+        .ok()
+        .flatten()?;
+    serde_json::from_str(&s).ok()
+}
+
+pub fn save_sync_cursor(uid: &str, cursor: &SyncCursor) {
+    save(
+        &format!("sync_cursor_{}", uid),
+        &serde_json::to_string(cursor).unwrap(),
+    );
+}
+
+/// Tasks edited on two devices at once, until the user has picked what to keep.
+pub fn conflicts() -> Vec<Conflict> {
+    let Some(s) = storage().get_item("conflicts").ok().flatten() else {
+        return vec![];
+    };
+    serde_json::from_str(&s).unwrap_or_else(|_| {
+        quarantine("conflicts", &s);
+        vec![]
+    })
+}
+
+pub fn save_conflicts(conflicts: &[Conflict]) {
+    guard("conflicts", |s| {
+        serde_json::from_str::<Vec<Conflict>>(s).is_ok()
+    });
+    save("conflicts", &serde_json::to_string(conflicts).unwrap());
+}
+
+/// Tasks kept off the server from before that was recorded in the journal, see
+/// `Tasks::load_offline`.
+pub fn local_only() -> HashSet<Uuid> {
+    let Some(s) = storage().get_item("local_only").ok().flatten() else {
+        return HashSet::new();
+    };
+    serde_json::from_str(&s).unwrap_or_else(|_| {
+        quarantine("local_only", &s);
+        HashSet::new()
+    })
+}
+
+pub fn clear_local_only() {
+    let _ = storage().remove_item("local_only");
+}
+
+/// Ids of the operations waiting to be uploaded.
+pub fn outbox() -> Vec<Uuid> {
+    let Some(s) = storage().get_item("outbox").ok().flatten() else {
+        return vec![];
+    };
+    serde_json::from_str(&s).unwrap_or_default()
+}
+
+pub fn save_outbox(ids: &[Uuid]) {
+    guard("outbox", |s| serde_json::from_str::<Vec<Uuid>>(s).is_ok());
+    save("outbox", &serde_json::to_string(ids).unwrap());
+}
+
+/// When this device last synced successfully with the user's data.
+pub fn last_synced(uid: &str) -> Option<Duration> {
+    let s = storage()
+        .get_item(&format!("last_synced_{}", uid))
+        .ok()
+        .flatten()?;
+    s.parse().ok().map(Duration::from_millis)
+}
+
+pub fn save_last_synced(uid: &str, time: Duration) {
+    save(
+        &format!("last_synced_{}", uid),
+        &time.as_millis().to_string(),
+    );
+}
+
+/// The sync server set in settings, synced with instead of Firestore.
+pub fn sync_server() -> Option<ServerConfig> {
+    let s = storage().get_item("sync_server").ok().flatten()?;
+    serde_json::from_str(&s).ok()
+}
+
+pub fn save_sync_server(config: Option<&ServerConfig>) {
+    match config {
+        Some(config) => save("sync_server", &serde_json::to_string(config).unwrap()),
+        None => {
+            let _ = storage().remove_item("sync_server");
+        }
+    }
+}
+
+/// Whether the tasks stored on the server before the journal have been imported for this user.
+pub fn legacy_imported(uid: &str) -> bool {
+    storage()
+        .get_item(&format!("legacy_imported_{}", uid))
+        .ok()
+        .flatten()
+        .is_some()
+}
+
+pub fn set_legacy_imported(uid: &str) {
+    save(&format!("legacy_imported_{}", uid), "true");
 }
 
 pub async fn fetch_logs() -> HashMap<Uuid, TaskLog> {
@@ -22,16 +312,23 @@ pub async fn fetch_logs() -> HashMap<Uuid, TaskLog> {
     let logs_str = load("logs").await;
     log_to_console("Completed localStorage call");
 
-    match logs_str {
+    let mut logs: HashMap<Uuid, TaskLog> = match logs_str {
         Some(str) => serde_json::from_str(&str).unwrap_or_else(|e| {
             log_to_console(&format!("Deserialization error: {:?}", e));
+            quarantine("logs", &str);
             HashMap::default()
         }),
         None => {
             log_to_console("No logs found in localStorage");
             HashMap::default()
         }
+    };
+
+    for log in logs.values_mut() {
+        log.assign_legacy_ids();
     }
+
+    logs
 }
 
 pub async fn fetch_tasks() -> HashMap<Uuid, Task> {
@@ -59,16 +356,23 @@ pub async fn fetch_metadata() -> HashMap<Uuid, MetaData> {
     let tasks_str = load("tasks").await;
     log_to_console("Completed localStorage call");
 
-    match tasks_str {
+    let mut metadata: HashMap<Uuid, MetaData> = match tasks_str {
         Some(str) => serde_json::from_str(&str).unwrap_or_else(|e| {
             log_to_console(&format!("Deserialization error: {:?}", e));
+            quarantine("tasks", &str);
             HashMap::default()
         }),
         None => {
             log_to_console("No tasks found in localStorage");
             HashMap::default()
         }
+    };
+
+    for metadata in metadata.values_mut() {
+        metadata.assign_legacy_versions();
     }
+
+    metadata
 }
 
 fn storage() -> Storage {
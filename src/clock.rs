@@ -0,0 +1,133 @@
+use crate::{cache, log, utils};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::time::Duration;
+
+type UnixTime = Duration;
+
+// Remote timestamps further ahead than this are from a broken clock, and would drag ours along.
+const MAX_DRIFT: Duration = Duration::from_secs(86400);
+
+/// How far off the device's clock can be from the server's before we warn about it.
+pub const SKEW_WARNING: Duration = Duration::from_secs(60);
+
+/// A hybrid logical clock timestamp. It follows the wall clock, but never goes backwards and
+/// always ends up ahead of every timestamp it has seen from other devices, so edits are ordered
+/// by what a device knew when making them rather than by how well its clock is set.
+#[derive(
+    Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct Hlc {
+    pub time: UnixTime,
+    #[serde(default)]
+    pub counter: u32,
+}
+
+impl From<UnixTime> for Hlc {
+    fn from(time: UnixTime) -> Self {
+        Self::new(time, 0)
+    }
+}
+
+impl Hlc {
+    pub fn new(time: UnixTime, counter: u32) -> Self {
+        Self { time, counter }
+    }
+
+    /// The smallest timestamp after this one.
+    pub fn successor(self) -> Self {
+        Self::new(self.time, self.counter + 1)
+    }
+
+    /// The timestamp of a local event.
+    pub fn tick(self, now: UnixTime) -> Self {
+        if now > self.time {
+            Self::from(now)
+        } else {
+            self.successor()
+        }
+    }
+
+    /// Moves past a timestamp from another device.
+    pub fn observe(self, remote: Self, now: UnixTime) -> Self {
+        if remote.time > now + MAX_DRIFT {
+            return self;
+        }
+
+        let local = self.max(remote);
+        if now > local.time {
+            Self::from(now)
+        } else {
+            local.successor()
+        }
+    }
+}
+
+thread_local! {
+    static CLOCK: Cell<Option<Hlc>> = const { Cell::new(None) };
+}
+
+fn update(f: impl FnOnce(Hlc, UnixTime) -> Hlc) -> Hlc {
+    CLOCK.with(|clock| {
+        let last = clock.get().or_else(cache::load_clock).unwrap_or_default();
+        let next = f(last, utils::current_time());
+        clock.set(Some(next));
+        cache::save_clock(next);
+        next
+    })
+}
+
+/// Stamps a change made on this device.
+pub fn tick() -> Hlc {
+    update(Hlc::tick)
+}
+
+/// Called with timestamps from other devices, so that our next changes are ordered after them.
+pub fn observe(remote: Hlc) {
+    update(|last, now| {
+        if remote.time > now + MAX_DRIFT {
+            log(("ignoring timestamp from the future: ", remote));
+        }
+        last.observe(remote, now)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> UnixTime {
+        UnixTime::from_millis(ms)
+    }
+
+    #[test]
+    fn test_tick() {
+        let start = Hlc::from(ms(1000));
+        assert_eq!(start.tick(ms(2000)), Hlc::new(ms(2000), 0));
+
+        // The wall clock went backwards, or two events in the same millisecond.
+        let next = start.tick(ms(500));
+        assert_eq!(next, Hlc::new(ms(1000), 1));
+        assert_eq!(next.tick(ms(1000)), Hlc::new(ms(1000), 2));
+    }
+
+    #[test]
+    fn test_observe_fast_clock() {
+        // The other device's clock is an hour ahead.
+        let local = Hlc::from(ms(1000));
+        let remote = Hlc::new(ms(3_601_000), 3);
+
+        let observed = local.observe(remote, ms(2000));
+        assert!(observed > remote);
+
+        // Later local edits come after the remote one, even though our wall clock says otherwise.
+        assert!(observed.tick(ms(3000)) > remote);
+    }
+
+    #[test]
+    fn test_observe_ignores_broken_clocks() {
+        let local = Hlc::from(ms(1000));
+        let remote = Hlc::from(ms(1000) + MAX_DRIFT * 2);
+        assert_eq!(local.observe(remote, ms(1000)), local);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::journal::{Op, OpKind};
+use crate::task::{Field, FieldVersions, MetaData, Task, TaskID, Version};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::mem::discriminant;
+
+/// A task whose fields were changed here and on another device at the same time. The merge rules
+/// have already picked the newer change of each, but both sides are kept until the user decides.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Conflict {
+    pub task: TaskID,
+    pub local: MetaData,
+    // The local metadata with the other device's changes to the fields in conflict.
+    pub remote: MetaData,
+}
+
+impl Conflict {
+    /// The fields the sides disagree on, as (mine, theirs).
+    pub fn fields(&self) -> Vec<(Field, Field)> {
+        self.remote
+            .changes(&self.local)
+            .into_iter()
+            .map(|theirs| (self.local.field(&theirs), theirs))
+            .collect()
+    }
+}
+
+/// The field an operation sets, for the fields that can conflict.
+fn field(op: &Op) -> Option<Field> {
+    match &op.kind {
+        // Whether to keep a task off the server isn't something to pick between.
+        OpKind::Edited(Field::LocalOnly(_)) => None,
+        OpKind::Edited(field) => Some(field.clone()),
+        OpKind::Deleted => Some(Field::Deleted(true)),
+        OpKind::Restored => Some(Field::Deleted(false)),
+        _ => None,
+    }
+}
+
+/// Sets the field whatever its version, the other side's versions don't matter for showing it.
+fn set(metadata: &mut MetaData, field: Field) {
+    match field {
+        Field::Name(name) => metadata.name = name,
+        Field::Value(value) => metadata.value = value,
+        Field::Length(length) => metadata.length = length,
+        Field::Deleted(deleted) => metadata.deleted = deleted,
+        Field::LocalOnly(local_only) => metadata.local_only = local_only,
+    }
+}
+
+/// Finds where operations from other devices change the same fields as operations made here
+/// since the last sync, to something else. Those can't have been made knowing about each
+/// other. Call before applying `remote`.
+pub fn detect(tasks: &HashMap<TaskID, Task>, pending: &[Op], remote: &[Op]) -> Vec<Conflict> {
+    let ids: HashSet<TaskID> = remote.iter().map(|op| op.task).collect();
+    let mut conflicts = vec![];
+
+    for id in ids {
+        let Some(task) = tasks.get(&id) else {
+            continue;
+        };
+
+        let mine: Vec<Field> = pending
+            .iter()
+            .filter(|op| op.task == id)
+            .filter_map(field)
+            .collect();
+        if mine.is_empty() {
+            continue;
+        }
+
+        // Their changes on top of ours regardless of version, keeping the newest of their own.
+        let mut theirs = task.metadata.clone();
+        theirs.versions = FieldVersions::all(Version::default());
+        for op in remote.iter().filter(|op| op.task == id) {
+            if let Some(field) = field(op) {
+                theirs.apply(field, op.version);
+            }
+        }
+
+        let mut remote = task.metadata.clone();
+        for field in theirs.changes(&task.metadata) {
+            if mine.iter().any(|m| discriminant(m) == discriminant(&field)) {
+                set(&mut remote, field);
+            }
+        }
+
+        if remote != task.metadata {
+            conflicts.push(Conflict {
+                task: id,
+                local: task.metadata.clone(),
+                remote,
+            });
+        }
+    }
+
+    conflicts
+}
+
+/// Adds newly found conflicts to the unresolved ones. A task already in conflict keeps its
+/// local side, since that's what the user had before any of it.
+pub fn merge(conflicts: &mut Vec<Conflict>, found: Vec<Conflict>) {
+    for conflict in found {
+        match conflicts.iter_mut().find(|c| c.task == conflict.task) {
+            Some(existing) => existing.remote = conflict.remote,
+            None => conflicts.push(conflict),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::apply_all;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    type UnixTime = Duration;
+
+    const TASK: Uuid = Uuid::from_u128(7);
+    const PHONE: Uuid = Uuid::from_u128(1);
+    const LAPTOP: Uuid = Uuid::from_u128(2);
+
+    fn version(secs: u64, device: Uuid) -> Version {
+        Version::new(UnixTime::from_secs(secs).into(), device)
+    }
+
+    fn tasks() -> HashMap<TaskID, Task> {
+        let version = version(100, PHONE);
+        let metadata = MetaData::test("dishes", version);
+        let mut tasks = HashMap::default();
+        apply_all(
+            &mut tasks,
+            &[Op::new(TASK, version, OpKind::Created(Box::new(metadata)))],
+        );
+        tasks
+    }
+
+    fn edit(secs: u64, device: Uuid, field: Field) -> Op {
+        Op::new(TASK, version(secs, device), OpKind::Edited(field))
+    }
+
+    #[test]
+    fn test_detect() {
+        let mut tasks = tasks();
+        let mine = [
+            edit(200, PHONE, Field::Name("plates".into())),
+            edit(200, PHONE, Field::Length(Duration::from_secs(300))),
+        ];
+        apply_all(&mut tasks, &mine);
+
+        let theirs = [
+            edit(250, LAPTOP, Field::Name("cups".into())),
+            edit(300, LAPTOP, Field::Name("bowls".into())),
+            // Only we changed the length, and only they deleted it.
+            Op::new(TASK, version(250, LAPTOP), OpKind::Deleted),
+        ];
+
+        let conflicts = detect(&tasks, &mine, &theirs);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            conflicts[0].fields(),
+            [(Field::Name("plates".into()), Field::Name("bowls".into()))]
+        );
+
+        // Nothing of ours is waiting, so they knew about it.
+        assert!(detect(&tasks, &[], &theirs).is_empty());
+        // They changed it to the same thing.
+        let same = [edit(250, LAPTOP, Field::Name("plates".into()))];
+        assert!(detect(&tasks, &mine, &same).is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let tasks = tasks();
+        let mine = [edit(200, PHONE, Field::Name("plates".into()))];
+        let first = detect(
+            &tasks,
+            &mine,
+            &[edit(250, LAPTOP, Field::Name("cups".into()))],
+        );
+        let second = detect(
+            &tasks,
+            &mine,
+            &[edit(300, LAPTOP, Field::Name("bowls".into()))],
+        );
+
+        let mut conflicts = vec![];
+        merge(&mut conflicts, first);
+        merge(&mut conflicts, second);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].remote.name, "bowls");
+    }
+}
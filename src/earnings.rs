@@ -0,0 +1,282 @@
+use crate::task::{val_calc, LogRecord, ValueEq, DEFAULT_SLOPE};
+use std::time::Duration;
+
+type UnixTime = Duration;
+
+const DAY: u64 = 86400;
+const LAMBDA: f32 = 0.8;
+
+/// The state needed to value the next log without looking at the ones before it.
+/// Logs have to be added in order of time.
+#[derive(Debug, Clone, Default)]
+pub struct Accumulator {
+    last_completed: Option<UnixTime>,
+    // Same weighting as `Contask::daily_average`, kept up to date one day at a time.
+    day: Option<u64>,
+    weighted_units: f32,
+    weight_total: f32,
+    // Contasks count as having been done at the average rate when they were created.
+    created_units: Option<(UnixTime, f32)>,
+}
+
+impl Accumulator {
+    pub fn new(params: &ValueEq) -> Self {
+        let created_units = match params {
+            ValueEq::Cont(c) => Some((c.created, c.daily_units)),
+            _ => None,
+        };
+
+        Self {
+            created_units,
+            ..Default::default()
+        }
+    }
+
+    /// Replays `records`, which must be sorted by time.
+    pub fn replay<'a>(params: &ValueEq, records: impl Iterator<Item = &'a LogRecord>) -> Self {
+        let mut acc = Self::new(params);
+        for record in records {
+            acc.add(record.time, record.units);
+        }
+        acc
+    }
+
+    pub fn add(&mut self, time: UnixTime, units: f32) {
+        self.catch_up(time);
+        self.add_units(time, units);
+        self.last_completed = Some(self.last_completed.map_or(time, |last| last.max(time)));
+    }
+
+    fn catch_up(&mut self, time: UnixTime) {
+        if let Some((created, units)) = self.created_units {
+            if created <= time {
+                self.created_units = None;
+                self.add_units(created, units);
+            }
+        }
+    }
+
+    fn add_units(&mut self, time: UnixTime, units: f32) {
+        self.advance(time.as_secs() / DAY);
+        self.weighted_units += units * (-LAMBDA).exp();
+    }
+
+    fn advance(&mut self, day: u64) {
+        let decay = (-LAMBDA).exp();
+
+        let Some(current) = self.day else {
+            self.day = Some(day);
+            self.weight_total = decay;
+            return;
+        };
+
+        for _ in current..day {
+            self.weighted_units *= decay;
+            self.weight_total = (self.weight_total + 1.) * decay;
+        }
+
+        self.day = Some(current.max(day));
+    }
+
+    /// What doing `units` at `time` earns, one unit at a time.
+    pub fn earned(&self, params: &ValueEq, created: UnixTime, time: UnixTime, units: f32) -> f32 {
+        let units = units as u32;
+
+        match params {
+            ValueEq::Const(f) => *f * units as f32,
+            ValueEq::Log(l) => {
+                if units == 0 {
+                    return 0.;
+                }
+
+                // Every unit after the first one is done right after the previous one, worth nothing.
+                let last_completed = self.last_completed.unwrap_or(created - l.interval);
+                l.value(time.saturating_sub(last_completed))
+            }
+            ValueEq::Cont(c) => {
+                let mut acc = self.clone();
+                acc.catch_up(time);
+                acc.advance(time.as_secs() / DAY);
+
+                let decay = (-LAMBDA).exp();
+                let mut earned = 0.;
+                for unit in 0..units {
+                    let avg = (acc.weighted_units + unit as f32 * decay) / acc.weight_total;
+                    earned += c.factor * val_calc::value(c.daily_units / avg, DEFAULT_SLOPE);
+                }
+                earned
+            }
+        }
+    }
+}
+
+/// What each log earned, with prefix sums so that any time window can be summed with a
+/// binary search.
+#[derive(Debug, Clone)]
+pub struct Earnings {
+    // Used for logs that don't have their earnings stored.
+    params: ValueEq,
+    created: UnixTime,
+    acc: Accumulator,
+    times: Vec<UnixTime>,
+    // `prefix[i]` is the sum of the first `i` logs.
+    prefix: Vec<f64>,
+}
+
+impl Earnings {
+    /// `records` must be sorted by time.
+    pub fn build<'a>(
+        records: impl Iterator<Item = &'a LogRecord>,
+        params: &ValueEq,
+        created: UnixTime,
+    ) -> Self {
+        let mut selv = Self {
+            params: params.clone(),
+            created,
+            acc: Accumulator::new(params),
+            times: vec![],
+            prefix: vec![0.],
+        };
+
+        for record in records {
+            selv.push(record);
+        }
+
+        selv
+    }
+
+    pub fn is_built_with(&self, params: &ValueEq, created: UnixTime) -> bool {
+        &self.params == params && self.created == created
+    }
+
+    /// Adds a record that's at least as new as every record so far.
+    /// Returns false if it isn't, in which case nothing is added.
+    pub fn push(&mut self, record: &LogRecord) -> bool {
+        if self.times.last().is_some_and(|last| *last > record.time) {
+            return false;
+        }
+
+        let earned = match record.earned {
+            Some(earned) => earned,
+            None => {
+                let params = record.params.as_ref().unwrap_or(&self.params);
+                self.acc
+                    .earned(params, self.created, record.time, record.units)
+            }
+        };
+
+        self.acc.add(record.time, record.units);
+        self.times.push(record.time);
+        self.prefix.push(self.total() + earned as f64);
+        true
+    }
+
+    fn total(&self) -> f64 {
+        *self.prefix.last().unwrap()
+    }
+
+    /// Sum of what was earned after `cutoff`.
+    pub fn since(&self, cutoff: UnixTime) -> f32 {
+        let idx = self.times.partition_point(|time| *time <= cutoff);
+        (self.total() - self.prefix[idx]) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::{Contask, LogPriority, TaskLog};
+
+    const START: u64 = DAY * 1000;
+
+    fn record(secs: u64, units: f32) -> LogRecord {
+        LogRecord {
+            time: UnixTime::from_secs(secs),
+            units,
+            ..Default::default()
+        }
+    }
+
+    fn records() -> Vec<LogRecord> {
+        vec![
+            record(START + DAY * 2, 10.),
+            record(START + DAY * 2 + 600, 5.),
+            record(START + DAY * 5, 30.),
+            record(START + DAY * 5 + 20, 1.),
+            record(START + DAY * 9, 12.),
+        ]
+    }
+
+    /// Values every unit by re-evaluating the whole log, the way it used to be done.
+    fn naive(params: &ValueEq, created: UnixTime, records: &[LogRecord]) -> Vec<f32> {
+        let mut out = vec![];
+
+        for (idx, rec) in records.iter().enumerate() {
+            let mut earned = 0.;
+            for unit in 0..(rec.units as u32) {
+                let mut before = records[..idx].to_vec();
+                if unit > 0 {
+                    before.push(record(rec.time.as_secs(), unit as f32));
+                }
+                let log: TaskLog = before.into();
+                earned += params.value(&log, created, rec.time);
+            }
+            out.push(earned);
+        }
+
+        out
+    }
+
+    fn assert_matches_naive(params: ValueEq) {
+        let created = UnixTime::from_secs(START + DAY);
+        let records = records();
+        let expected = naive(&params, created, &records);
+        let earnings = Earnings::build(records.iter(), &params, created);
+
+        for (idx, rec) in records.iter().enumerate() {
+            let cutoff = rec.time - UnixTime::from_secs(1);
+            let want: f32 = expected[idx..].iter().sum();
+            let got = earnings.since(cutoff);
+            assert!(
+                (want - got).abs() <= want.abs() * 1e-4,
+                "cutoff {:?}: want {}, got {}",
+                cutoff,
+                want,
+                got
+            );
+        }
+
+        assert_eq!(earnings.since(UnixTime::from_secs(START + DAY * 100)), 0.);
+    }
+
+    #[test]
+    fn test_log_earnings() {
+        let params = ValueEq::Log(LogPriority::new(10., UnixTime::from_secs(DAY * 2)));
+        assert_matches_naive(params);
+    }
+
+    #[test]
+    fn test_cont_earnings() {
+        let params = ValueEq::Cont(Contask {
+            daily_units: 8.,
+            factor: 0.5,
+            created: UnixTime::from_secs(START + DAY),
+            unit_name: None,
+        });
+        assert_matches_naive(params);
+    }
+
+    #[test]
+    fn test_stored_earnings_and_push() {
+        let params = ValueEq::Const(2.);
+        let mut stored = record(DAY, 3.);
+        stored.earned = Some(100.);
+
+        let mut earnings = Earnings::build([stored].iter(), &params, UnixTime::ZERO);
+        assert!(earnings.push(&record(DAY * 2, 2.)));
+        assert!(!earnings.push(&record(DAY, 2.)));
+
+        assert_eq!(earnings.since(UnixTime::ZERO), 104.);
+        assert_eq!(earnings.since(UnixTime::from_secs(DAY)), 4.);
+    }
+}
@@ -1,23 +1,35 @@
 use js_sys::Promise;
+use std::time::Duration;
 use uuid::Uuid;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
 
-use crate::task::LogRecord;
+use crate::journal::Op;
+use crate::schema;
+use crate::task::{LogRecord, MetaData};
 use crate::AuthUser;
-use crate::Task;
 
 #[wasm_bindgen(module = "/assets/firestore.js")]
 extern "C" {
-    fn upsertFirestoreTask(user_id: &JsValue, id: &JsValue, task: &JsValue) -> Promise;
     fn loadAllTasks(user_id: &JsValue) -> Promise;
+    fn upsertFirestoreTask(user_id: &JsValue, id: &JsValue, task: &JsValue) -> Promise;
     fn addFirestoreTaskLog(
         user_id: &JsValue,
         task_id: &JsValue,
         log_id: &JsValue,
-        log_factor: &JsValue,
+        log: &JsValue,
     ) -> Promise;
+    fn addFirestoreOps(user_id: &JsValue, ops: &JsValue) -> Promise;
+    fn loadOps(user_id: &JsValue, since_millis: &JsValue) -> Promise;
+    fn deleteTaskRemotely(user_id: &JsValue, task_id: &JsValue, op_ids: &JsValue) -> Promise;
+    fn subscribeOps(
+        user_id: &JsValue,
+        since_millis: f64,
+        on_ops: &Closure<dyn FnMut(JsValue)>,
+    ) -> js_sys::Function;
     fn loadLogsForTask(user_id: &JsValue, task_id: &JsValue) -> Promise;
+    fn loadAllLogs(user_id: &JsValue) -> Promise;
+    fn probeServerTime(user_id: &JsValue) -> Promise;
     fn isUserAuthenticated() -> Promise;
     fn signInWithGoogle() -> Promise;
     fn signOutUser() -> Promise;
@@ -36,6 +48,12 @@ pub fn load_all_tasks(user: &AuthUser) -> JsFuture {
     wasm_bindgen_futures::JsFuture::from(promise)
 }
 
+pub fn probe_server_time(user: &AuthUser) -> JsFuture {
+    let uid = JsValue::from_str(&user.uid);
+    let promise = probeServerTime(&uid);
+    wasm_bindgen_futures::JsFuture::from(promise)
+}
+
 pub fn is_authed() -> JsFuture {
     let promise = isUserAuthenticated();
     wasm_bindgen_futures::JsFuture::from(promise)
@@ -50,41 +68,137 @@ pub fn load_logs_for_task(user_id: String, task_id: Uuid) -> JsFuture {
     wasm_bindgen_futures::JsFuture::from(promise)
 }
 
-pub fn add_task_log_to_firestore(user_id: String, task_id: Uuid, log: LogRecord) -> JsFuture {
-    let task_id_str = task_id.to_string();
-    let log_id_str = log.time.as_secs().to_string();
-    let unit_str = log.units.to_string();
+/// Every operation, or the ones the server stored after `since`.
+pub fn load_ops(user: &AuthUser, since: Option<Duration>) -> JsFuture {
+    let uid = JsValue::from_str(&user.uid);
+    let since = since.map_or(JsValue::NULL, |since| {
+        JsValue::from_f64(since.as_millis() as f64)
+    });
+    let promise = loadOps(&uid, &since);
+    wasm_bindgen_futures::JsFuture::from(promise)
+}
 
-    let user_id = JsValue::from_str(&user_id);
-    let task_id = JsValue::from_str(&task_id_str);
-    let log_id = JsValue::from_str(&log_id_str);
-    let unit = JsValue::from_str(&unit_str);
+/// Deletes the task's operations and legacy documents from the server.
+pub fn delete_task_remotely(user: &AuthUser, task: Uuid, ops: &[Uuid]) -> JsFuture {
+    let uid = JsValue::from_str(&user.uid);
+    let task = JsValue::from_str(&task.to_string());
+    let ops: js_sys::Array = ops
+        .iter()
+        .map(|id| JsValue::from_str(&id.to_string()))
+        .collect();
+    let promise = deleteTaskRemotely(&uid, &task, &ops);
+    wasm_bindgen_futures::JsFuture::from(promise)
+}
 
-    let promise = addFirestoreTaskLog(&user_id, &task_id, &log_id, &unit);
+/// Listens for operations as the server stores them. Stops once dropped.
+pub struct Subscription {
+    unsubscribe: js_sys::Function,
+    _on_ops: Closure<dyn FnMut(JsValue)>,
+}
 
-    wasm_bindgen_futures::JsFuture::from(promise)
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let _ = self.unsubscribe.call0(&JsValue::NULL);
+    }
 }
 
-pub fn send_task_to_firestore(user_id: String, task: &Task) -> JsFuture {
-    let id = task.id;
-    let task = task.metadata.clone();
+/// Calls `on_ops` with the operations stored after `since`, and with new ones as they come.
+pub fn subscribe_ops(
+    user: &AuthUser,
+    since: Duration,
+    on_ops: impl FnMut(JsValue) + 'static,
+) -> Subscription {
+    let uid = JsValue::from_str(&user.uid);
+    let on_ops = Closure::new(on_ops);
+    let unsubscribe = subscribeOps(&uid, since.as_millis() as f64, &on_ops);
+    Subscription {
+        unsubscribe,
+        _on_ops: on_ops,
+    }
+}
 
-    let taskstr = serde_json::to_string(&task).unwrap();
+pub fn load_all_logs(user: &AuthUser) -> JsFuture {
+    let uid = JsValue::from_str(&user.uid);
+    let promise = loadAllLogs(&uid);
+    wasm_bindgen_futures::JsFuture::from(promise)
+}
 
+/// Writes the task's metadata to the collection clients from before the journal read.
+pub fn send_task_to_firestore(user_id: &str, id: Uuid, metadata: &MetaData) -> JsFuture {
     let task = js_sys::Object::new();
     js_sys::Reflect::set(
         &task,
         &JsValue::from_str("task"),
-        &JsValue::from_str(&taskstr),
+        &JsValue::from_str(&serde_json::to_string(metadata).unwrap()),
+    )
+    .unwrap();
+    js_sys::Reflect::set(
+        &task,
+        &JsValue::from_str("v"),
+        &JsValue::from_f64(schema::METADATA.version() as f64),
     )
     .unwrap();
 
-    let idstr = serde_json::to_string(&id).unwrap();
+    let user_id = JsValue::from_str(user_id);
+    let id = JsValue::from_str(&serde_json::to_string(&id).unwrap());
+    let promise = upsertFirestoreTask(&user_id, &id, &task);
+    wasm_bindgen_futures::JsFuture::from(promise)
+}
 
-    let user_id = JsValue::from_str(&user_id);
-    let id = JsValue::from_str(&idstr);
+/// Writes the record to the collection clients from before the journal read, in the shape they
+/// expect, see `LogRecord::legacy_doc`.
+pub fn add_task_log_to_firestore(user_id: &str, task_id: Uuid, log: &LogRecord) -> JsFuture {
+    let (log_id, doc) = log.legacy_doc();
+    let fields = js_sys::Object::new();
+    for (key, val) in doc.as_object().unwrap() {
+        let val = match val {
+            serde_json::Value::Bool(b) => JsValue::from_bool(*b),
+            val => JsValue::from_str(val.as_str().unwrap()),
+        };
+        js_sys::Reflect::set(&fields, &JsValue::from_str(key), &val).unwrap();
+    }
+
+    let user_id = JsValue::from_str(user_id);
+    let task_id = JsValue::from_str(&task_id.to_string());
+    let log_id = JsValue::from_str(&log_id);
+    let promise = addFirestoreTaskLog(&user_id, &task_id, &log_id, &fields);
+    wasm_bindgen_futures::JsFuture::from(promise)
+}
 
-    let promise = upsertFirestoreTask(&user_id, &id, &task);
-    let future = wasm_bindgen_futures::JsFuture::from(promise);
-    future
+fn op_fields(op: &Op) -> js_sys::Object {
+    let fields = js_sys::Object::new();
+    js_sys::Reflect::set(
+        &fields,
+        &JsValue::from_str("op"),
+        &JsValue::from_str(&serde_json::to_string(op).unwrap()),
+    )
+    .unwrap();
+    js_sys::Reflect::set(
+        &fields,
+        &JsValue::from_str("v"),
+        &JsValue::from_f64(schema::OP.version() as f64),
+    )
+    .unwrap();
+    fields
+}
+
+/// Writes the operations in one batch. Resolves to whether each of them was written, see
+/// `addFirestoreOps`.
+pub fn add_ops_to_firestore(user_id: &str, ops: &[Op]) -> JsFuture {
+    let docs = js_sys::Array::new();
+    for op in ops {
+        let doc = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &doc,
+            &JsValue::from_str("id"),
+            &JsValue::from_str(&op.id.to_string()),
+        )
+        .unwrap();
+        js_sys::Reflect::set(&doc, &JsValue::from_str("fields"), &op_fields(op)).unwrap();
+        docs.push(&doc);
+    }
+
+    let user_id = JsValue::from_str(user_id);
+    let promise = addFirestoreOps(&user_id, &docs);
+    wasm_bindgen_futures::JsFuture::from(promise)
 }
@@ -0,0 +1,117 @@
+#![allow(non_snake_case)]
+
+use super::*;
+
+use crate::backup::{self, BackupInfo};
+use crate::journal::Journal;
+use crate::State;
+
+#[component]
+fn BackupRow(info: BackupInfo) -> Element {
+    let state = use_context::<State>();
+    let mut store = state.inner.lock().unwrap().tasks;
+    let navigator = use_navigator();
+    let mut diff = use_signal(|| None::<Vec<String>>);
+
+    let taken = info.taken;
+    let when = utils::datetime_input_str(taken).replace('T', " ");
+    let size = format!("{:.0}kB", info.bytes as f32 / 1000.);
+    let raw = cache::load_backup(taken)?;
+    let href = download_href(&raw);
+    let filename = format!("firelog-backup-{}.json", when.replace([' ', ':'], "-"));
+
+    rsx! {
+        li {
+            margin_bottom: "20px",
+
+            div { "{when}" }
+            div {
+                color: "#666",
+                "{info.tasks} tasks, {info.logs} logs, {size}"
+            }
+
+            button {
+                onclick: {
+                    let raw = raw.clone();
+                    move |_| {
+                        if diff().is_some() {
+                            diff.set(None);
+                            return;
+                        }
+                        let lines = match Journal::from_json(&raw) {
+                            Ok(journal) => backup::diff(&journal.replay(), store.read().by_id()),
+                            Err(e) => vec![format!("can't read this backup: {}", e)],
+                        };
+                        diff.set(Some(lines));
+                    }
+                },
+                "diff"
+            }
+            button {
+                margin_left: "10px",
+                onclick: move |_| {
+                    let Ok(journal) = Journal::from_json(&raw) else {
+                        return;
+                    };
+
+                    let confirmed = web_sys::window()
+                        .and_then(|window| {
+                            window
+                                .confirm_with_message("Restore the tasks to how they were in this backup? You can undo it afterwards.")
+                                .ok()
+                        })
+                        .unwrap_or(false);
+
+                    if confirmed {
+                        store.write().restore(&journal);
+                        navigator.replace(Route::Home {});
+                    }
+                },
+                "restore"
+            }
+            a {
+                margin_left: "10px",
+                href: href,
+                download: filename,
+                "download"
+            }
+
+            if let Some(lines) = diff() {
+                if lines.is_empty() {
+                    p { "same as now" }
+                }
+                for line in lines {
+                    div { "{line}" }
+                }
+            }
+        }
+    }
+}
+
+/// Snapshots of the tasks taken on this device, whether or not it's signed in.
+#[component]
+pub fn Backups() -> Element {
+    let mut backups = cache::backup_index();
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.taken));
+
+    rsx! {
+        Link {to: Route::Home{}, "back"}
+
+        p {
+            "A backup is taken every day you use the app. Daily ones are kept for a week, then one a week for two months."
+        }
+
+        if backups.is_empty() {
+            p { "no backups yet" }
+        }
+
+        ul {
+            padding: "0",
+            list_style_type: "none",
+
+            for info in backups {
+                BackupRow { key: "{info.taken.as_millis()}", info }
+            }
+        }
+    }
+}
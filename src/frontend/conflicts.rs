@@ -0,0 +1,143 @@
+#![allow(non_snake_case)]
+
+use super::*;
+
+use crate::conflict::Conflict;
+use crate::task::Field;
+use crate::State;
+
+fn field_name(field: &Field) -> &'static str {
+    match field {
+        Field::Name(_) => "name",
+        Field::Value(_) => "value",
+        Field::Length(_) => "length",
+        Field::Deleted(_) => "deleted",
+        Field::LocalOnly(_) => "local only",
+    }
+}
+
+fn field_str(field: &Field) -> String {
+    match field {
+        Field::Name(name) => name.clone(),
+        Field::Value(value) => value_str(value),
+        Field::Length(length) => utils::dur_format(*length),
+        Field::Deleted(deleted) => if *deleted { "deleted" } else { "not deleted" }.to_string(),
+        Field::LocalOnly(local_only) => if *local_only {
+            "on this device only"
+        } else {
+            "synced"
+        }
+        .to_string(),
+    }
+}
+
+/// The two sides of a conflict next to each other, with a pick for each field.
+#[component]
+fn ConflictRow(conflict: Conflict) -> Element {
+    let state = use_context::<State>();
+    let mut store = state.inner.lock().unwrap().tasks;
+    let mut conflicts = state.inner.lock().unwrap().conflicts;
+
+    let fields = conflict.fields();
+    let count = fields.len();
+    // Whether their side is picked, for each field.
+    let mut theirs = use_signal(|| vec![false; count]);
+
+    let id = conflict.task;
+    let mut resolve = move |picked: Vec<Field>| {
+        store.write().resolve(id, picked);
+        conflicts.write().retain(|c| c.task != id);
+        cache::save_conflicts(&conflicts.peek());
+    };
+
+    let picked: Vec<Field> = fields
+        .iter()
+        .zip(theirs())
+        .map(|((mine, other), take)| if take { other.clone() } else { mine.clone() })
+        .collect();
+
+    rsx! {
+        li {
+            margin_bottom: "20px",
+
+            div { "{conflict.local.name}" }
+
+            table {
+                font_size: "0.8em",
+                tr {
+                    th {}
+                    th { "this device" }
+                    th { "other device" }
+                }
+                for (i, (mine, other)) in fields.into_iter().enumerate() {
+                    tr {
+                        td { "{field_name(&mine)}" }
+                        td {
+                            button {
+                                font_weight: if !theirs()[i] { "bold" } else { "normal" },
+                                onclick: move |_| theirs.write()[i] = false,
+                                "{field_str(&mine)}"
+                            }
+                        }
+                        td {
+                            button {
+                                font_weight: if theirs()[i] { "bold" } else { "normal" },
+                                onclick: move |_| theirs.write()[i] = true,
+                                "{field_str(&other)}"
+                            }
+                        }
+                    }
+                }
+            }
+
+            button {
+                onclick: {
+                    let mine: Vec<Field> = conflict.fields().into_iter().map(|(mine, _)| mine).collect();
+                    move |_| resolve(mine.clone())
+                },
+                "keep mine"
+            }
+            button {
+                margin_left: "10px",
+                onclick: {
+                    let fields = conflict.remote.changes(&conflict.local);
+                    move |_| resolve(fields.clone())
+                },
+                "take theirs"
+            }
+            button {
+                margin_left: "10px",
+                onclick: move |_| resolve(picked.clone()),
+                "keep picked"
+            }
+        }
+    }
+}
+
+/// Tasks edited on two devices at once. The newer edit is shown until one is picked here.
+#[component]
+pub fn Conflicts() -> Element {
+    let state = use_context::<State>();
+    let conflicts = state.inner.lock().unwrap().conflicts;
+
+    rsx! {
+        Link {to: Route::Home{}, "back"}
+
+        p {
+            "These tasks were changed on this device and another one at the same time. Pick which version of each change to keep."
+        }
+
+        if conflicts.read().is_empty() {
+            p { "no conflicts" }
+        }
+
+        ul {
+            padding: "0",
+            list_style_type: "none",
+
+            for conflict in conflicts() {
+                ConflictRow { key: "{conflict.task}", conflict }
+            }
+        }
+    }
+}
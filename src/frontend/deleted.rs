@@ -0,0 +1,52 @@
+#![allow(non_snake_case)]
+
+use super::*;
+
+use crate::State;
+
+#[component]
+pub fn Deleted() -> Element {
+    let state = use_context::<State>();
+    let mut store = state.inner.lock().unwrap().tasks;
+
+    let mut deleted: Vec<(Uuid, String)> = store
+        .read()
+        .all()
+        .filter(|task| task.metadata.deleted)
+        .map(|task| (task.id, task.metadata.name.clone()))
+        .collect();
+    deleted.sort_by(|a, b| a.1.cmp(&b.1));
+
+    rsx! {
+        Link {to: Route::Home{}, "back"}
+
+        if deleted.is_empty() {
+            p { "no deleted tasks" }
+        }
+
+        ul {
+            padding: "0",
+            list_style_type: "none",
+
+            for (id, name) in deleted {
+                li {
+                    key: "{id}",
+                    display: "flex",
+                    flex_direction: "row",
+                    align_items: "center",
+                    margin_bottom: "10px",
+
+                    span { "{name}" }
+                    button {
+                        class: "confirm",
+                        margin_left: "10px",
+                        onclick: move |_| {
+                            store.write().restore_task(id);
+                        },
+                        "restore"
+                    }
+                }
+            }
+        }
+    }
+}
@@ -2,15 +2,147 @@
 
 use super::*;
 
-use crate::task::{Task, Tasks};
+use crate::cache;
+use crate::sync;
+use crate::task::{MetaData, Task, ValueEq, Version};
 use crate::utils;
 use crate::State;
 use uuid::Uuid;
 
+/// Keeps the task off the server, see `sync::set_local_only`.
+#[component]
+fn LocalOnlyToggle(id: Uuid) -> Element {
+    let state = use_context::<State>();
+    let store = state.inner.lock().unwrap().tasks;
+    let local_only = store
+        .read()
+        .get_task(id)
+        .is_some_and(|task| task.metadata.local_only);
+
+    rsx! {
+        label {
+            display: "block",
+            input {
+                r#type: "checkbox",
+                checked: local_only,
+                onchange: move |evt| {
+                    let checked = evt.checked();
+                    let delete_remote = checked
+                        && state.auth_user().is_some()
+                        && web_sys::window()
+                            .and_then(|window| {
+                                window
+                                    .confirm_with_message("Also delete the copy on the server? Devices that already have this task keep their copy, but stop uploading it once they've synced.")
+                                    .ok()
+                            })
+                            .unwrap_or(false);
+                    sync::set_local_only(&state, id, checked, delete_remote);
+                },
+            }
+            "keep on this device only"
+        }
+    }
+}
+
+pub fn value_str(value: &ValueEq) -> String {
+    match value {
+        ValueEq::Log(l) => format!(
+            "{} every {}",
+            utils::format_float(l.factor),
+            utils::dur_format(l.interval)
+        ),
+        ValueEq::Cont(c) => format!(
+            "{} for {} {} a day",
+            utils::format_float(c.factor),
+            c.daily_units,
+            c.unit_name.as_deref().unwrap_or("units")
+        ),
+        ValueEq::Const(f) => utils::format_float(*f),
+    }
+}
+
+/// What changed since the previous version, or everything for the first one.
+fn version_str(metadata: &MetaData, previous: Option<&MetaData>) -> Vec<String> {
+    let mut lines = vec![];
+    if previous.is_none_or(|p| p.name != metadata.name) {
+        lines.push(format!("name: {}", metadata.name));
+    }
+    if previous.is_none_or(|p| p.value != metadata.value) {
+        lines.push(format!("value: {}", value_str(&metadata.value)));
+    }
+    if previous.is_none_or(|p| p.length != metadata.length) {
+        lines.push(format!("length: {}", utils::dur_format(metadata.length)));
+    }
+    lines
+}
+
+fn device_str(version: &Version) -> String {
+    if version.device.is_nil() {
+        "unknown device".to_string()
+    } else if version.device == cache::device_id() {
+        "this device".to_string()
+    } else {
+        let id = version.device.simple().to_string();
+        format!("device {}", &id[..8])
+    }
+}
+
+/// Every saved version of the task, newest first, with what changed in it.
+#[component]
+fn EditHistory(id: Uuid) -> Element {
+    let state = use_context::<State>();
+    let mut store = state.inner.lock().unwrap().tasks;
+    let versions = store.read().journal().versions(id);
+
+    let mut rows = vec![];
+    for (idx, (version, metadata)) in versions.iter().enumerate() {
+        let previous = idx.checked_sub(1).map(|prev| &versions[prev].1);
+        let when = utils::datetime_input_str(version.clock.time).replace('T', " ");
+        let current = idx + 1 == versions.len();
+        rows.push((
+            *version,
+            format!("{}, {}", when, device_str(version)),
+            version_str(metadata, previous),
+            current,
+        ));
+    }
+    rows.reverse();
+
+    rsx! {
+        details {
+            margin_top: "20px",
+            summary { "edit history" }
+
+            for (version, heading, lines, current) in rows {
+                div {
+                    margin_top: "10px",
+                    div {
+                        color: "#666",
+                        "{heading}"
+                    }
+                    for line in lines {
+                        div { "{line}" }
+                    }
+                    if !current {
+                        button {
+                            onclick: move |_| {
+                                store.write().revert_to(id, version);
+                            },
+                            "revert to this"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
 #[component]
 pub fn Editcont(id: Uuid) -> Element {
-    let task = Tasks::load_offline().get_task(id).unwrap();
-    let thetask = Tasks::load_offline().get_task(id).unwrap();
+    let state = use_context::<State>();
+    let mut store = state.inner.lock().unwrap().tasks;
+    let task = store.read().get_task(id).unwrap();
+    let thetask = task.clone();
 
     let navigator = use_navigator();
 
@@ -28,14 +160,11 @@ pub fn Editcont(id: Uuid) -> Element {
         oldtask.set_unit_name(newtask.unit_name());
         oldtask.metadata.name = newtask.metadata.name;
         oldtask.metadata.length = newtask.metadata.length;
-        oldtask.metadata.updated = utils::current_time();
 
-        let mut all_tasks = Tasks::load_offline();
-        all_tasks.insert(oldtask.clone());
-        all_tasks.save_offline();
+        let mut store = store;
+        store.write().edit_metadata(id, oldtask.metadata.clone());
 
         navigator.replace(Route::Home {});
-        State::refresh();
     };
 
     let form = rsx! {
@@ -63,8 +192,7 @@ pub fn Editcont(id: Uuid) -> Element {
     button {
         class: "emoji-button",
         onclick: move |_| {
-            Tasks::load_offline().delete_task(id);
-            State::refresh();
+            store.write().delete_task(id);
             navigator.replace(Route::Home{});
         },
         img {
@@ -76,14 +204,21 @@ pub fn Editcont(id: Uuid) -> Element {
     }
         }
 
+    Link { to: Route::History { id }, "history" }
+    LocalOnlyToggle { id }
+
     { form }
 
+    EditHistory { id }
+
     }
 }
 
 #[component]
 pub fn Edit(id: Uuid) -> Element {
-    let task = Tasks::load_offline().get_task(id).unwrap();
+    let state = use_context::<State>();
+    let mut store = state.inner.lock().unwrap().tasks;
+    let task = store.read().get_task(id).unwrap();
 
     let oldtask = task.clone();
     log(&oldtask);
@@ -100,15 +235,12 @@ pub fn Edit(id: Uuid) -> Element {
         oldtask.set_interval(newtask.interval());
         oldtask.metadata.name = newtask.metadata.name;
         oldtask.metadata.length = newtask.metadata.length;
-        oldtask.metadata.updated = utils::current_time();
         log(("edited task: ", &oldtask));
 
-        let mut all_tasks = Tasks::load_offline();
-        all_tasks.insert(oldtask.clone());
-        all_tasks.save_offline();
+        let mut store = store;
+        store.write().edit_metadata(id, oldtask.metadata.clone());
 
         navigator.replace(Route::Home {});
-        State::refresh();
     };
 
     let form = rsx! {
@@ -137,8 +269,7 @@ pub fn Edit(id: Uuid) -> Element {
                 class: "emoji-button",
                 margin_left: "20px",
                 onclick: move |_| {
-                    Tasks::load_offline().delete_task(id);
-                    State::refresh();
+                    store.write().delete_task(id);
                     navigator.replace(Route::Home{});
                 },
                 img {
@@ -149,7 +280,12 @@ pub fn Edit(id: Uuid) -> Element {
             }
         }
 
+    Link { to: Route::History { id }, "history" }
+    LocalOnlyToggle { id }
+
     { form }
 
+    EditHistory { id }
+
     }
 }
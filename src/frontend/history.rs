@@ -0,0 +1,149 @@
+#![allow(non_snake_case)]
+
+use super::*;
+
+use crate::utils;
+use crate::State;
+use std::time::Duration;
+use uuid::Uuid;
+
+#[component]
+pub fn History(id: Uuid) -> Element {
+    let state = use_context::<State>();
+    let mut store = state.inner.lock().unwrap().tasks;
+    let task = store.read().get_task(id).unwrap();
+    let navigator = use_navigator();
+
+    let mut records: Vec<(Uuid, Duration, f32, Option<f32>)> = task
+        .log
+        .records()
+        .map(|rec| (rec.id, rec.time, rec.units, rec.earned))
+        .collect();
+    records.reverse();
+
+    rsx! {
+        div {
+            display: "flex",
+            flex_direction: "row",
+            align_items: "center",
+
+            button {
+                class: "emoji-button",
+                onclick: move |_| {
+                    navigator.replace(Route::Home{});
+                },
+                img {
+                    width: "34px",
+                    height: "34px",
+                    src: "{back_str()}",
+                }
+            }
+
+            p {
+                margin_left: "10px",
+                "{task.metadata.name}"
+            }
+        }
+
+        if records.is_empty() {
+            p { "nothing logged yet" }
+        } else {
+            button {
+                margin_bottom: "10px",
+                onclick: move |_| {
+                    let confirmed = web_sys::window()
+                        .and_then(|window| {
+                            window
+                                .confirm_with_message("Recalculate what every entry earned using the task's current value?")
+                                .ok()
+                        })
+                        .unwrap_or(false);
+
+                    if confirmed {
+                        store.write().recalculate_earnings(id);
+                    }
+                },
+                "recalculate history"
+            }
+        }
+
+        ul {
+            padding: "0",
+            margin: "0",
+            list_style_type: "none",
+            max_height: "60vh",
+            overflow_y: "auto",
+
+            for (record_id, record_time, record_units, earned) in records {
+                li {
+                    key: "{record_id}",
+                    margin_bottom: "10px",
+
+                    form {
+                        flex_direction: "row",
+                        align_items: "center",
+                        onsubmit: move |event| {
+                            let data = event.data().values();
+                            let time_str = data.get("time").unwrap().as_value();
+                            let units_str = data.get("units").unwrap().as_value();
+
+                            // The input only has minute precision, so only move the record if the user changed it.
+                            let new_time = if time_str == utils::datetime_input_str(record_time) {
+                                record_time
+                            } else {
+                                match utils::parse_datetime_input(&time_str) {
+                                    Some(time) if time <= utils::current_time() => time,
+                                    _ => return,
+                                }
+                            };
+
+                            let Ok(units) = units_str.parse::<f32>() else {
+                                return;
+                            };
+
+                            store.write().edit_log(id, record_id, new_time, units);
+                        },
+
+                        input {
+                            r#type: "datetime-local",
+                            name: "time",
+                            value: utils::datetime_input_str(record_time),
+                            width: "170px",
+                        }
+                        input {
+                            r#type: "number",
+                            name: "units",
+                            value: record_units.to_string(),
+                            step: "any",
+                            autocomplete: "off",
+                            width: "50px",
+                        }
+                        if let Some(earned) = earned {
+                            span {
+                                color: "#666",
+                                "💸{utils::format_float(earned)}"
+                            }
+                        }
+                        button {
+                            r#type: "submit",
+                            class: "confirm",
+                            "save"
+                        }
+                        button {
+                            r#type: "button",
+                            class: "emoji-button",
+                            onclick: move |_| {
+                                store.write().delete_log(id, record_id);
+                            },
+                            img {
+                                width: "20px",
+                                height: "20px",
+                                src: "{delete_str()}",
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -2,24 +2,97 @@
 
 use super::*;
 
+use crate::clock;
 use crate::firebase;
-use crate::sync::sync_tasks;
-use crate::task::Tasks;
+use crate::sync::{sync_tasks, SyncStatus};
 use crate::utils;
 use crate::State;
+use gloo::events::EventListener;
+use gloo::timers::future::TimeoutFuture;
+use gloo::utils::document;
+use std::rc::Rc;
+
+// Tasks are positioned absolutely so that they slide into place when they overtake each other.
+const ROW_HEIGHT: usize = 40;
+const LIST_TOP: usize = 40;
+
+/// The current time, refreshed every `tick_rate` seconds while the page is visible.
+fn use_clock(tick_rate: Signal<String>) -> (Signal<Duration>, UseFuture) {
+    let mut now = use_signal(utils::current_time);
+
+    let mut ticker = use_future(move || async move {
+        // Catches up right away when the page becomes visible again.
+        now.set(utils::current_time());
+
+        let Ok(secs) = tick_rate.peek().parse::<u32>() else {
+            return;
+        };
+
+        loop {
+            TimeoutFuture::new(secs * 1000).await;
+            now.set(utils::current_time());
+        }
+    });
+
+    use_hook(move || {
+        Rc::new(EventListener::new(
+            &document(),
+            "visibilitychange",
+            move |_| {
+                if document().hidden() {
+                    ticker.cancel();
+                } else {
+                    ticker.restart();
+                }
+            },
+        ))
+    });
+
+    (now, ticker)
+}
 
 #[component]
 pub fn Home() -> Element {
     let state = use_context::<State>();
 
-    let mut tasks = state.inner.lock().unwrap().tasks.clone();
-    let mut value_stuff = state.inner.lock().unwrap().value_stuff.clone();
-    let valueform = format!("💸{}", utils::format_float(value_stuff()));
+    let mut store = state.inner.lock().unwrap().tasks;
     let mut auth = state.inner.lock().unwrap().auth_status.clone();
-    let is_syncing = state.inner.lock().unwrap().is_syncing.clone();
+    let sync_status = state.inner.lock().unwrap().sync_status;
     let mut selected_value = state.inner.lock().unwrap().selected_dur.clone();
+    let mut tick_rate = state.inner.lock().unwrap().tick_rate;
+    let clock_skew = state.inner.lock().unwrap().clock_skew;
+    let upload_failures = state.inner.lock().unwrap().upload_failures;
+    let pending_uploads = state.inner.lock().unwrap().pending_uploads;
+    let conflicts = state.inner.lock().unwrap().conflicts;
+    let skew_warning = clock_skew().and_then(|skew| {
+        let off = Duration::from_millis(skew.unsigned_abs());
+        let direction = if skew > 0 { "behind" } else { "ahead of" };
+        (off >= clock::SKEW_WARNING).then(|| {
+            format!(
+                "⚠️ this device's clock is {} {} the server's",
+                utils::dur_format(off),
+                direction
+            )
+        })
+    });
+    let (mut now, mut ticker) = use_clock(tick_rate);
+    let has_quarantined = use_hook(|| !cache::quarantined().is_empty());
+
+    let tasks = use_memo(move || task_props(&store.read(), now()));
+    let value_stuff = use_memo(move || {
+        let dur = utils::value_since(&selected_value.read());
+        tot_value_since(&store.read(), dur, now())
+    });
+
+    // Rendered in a fixed order with their rank as the position, so a reorder is just a change of `top`.
+    let mut rows: Vec<(usize, TaskProp)> = tasks().into_iter().enumerate().collect();
+    rows.sort_by_key(|(_, task)| task.id);
+    let list_height = LIST_TOP + rows.len() * ROW_HEIGHT;
+    let valueform = format!("💸{}", utils::format_float(value_stuff()));
 
     let navigator = use_navigator();
+    let mut when = use_signal(String::new);
+    let mut when_error = use_signal(|| None::<&'static str>);
 
     rsx! {
         div {
@@ -32,14 +105,10 @@ pub fn Home() -> Element {
                 button {
                     class: "emoji-button",
                     onclick: move |_| {
-                        sync_tasks(is_syncing.clone());
-                        tasks.set(task_props());
-                        let x = selected_value.read();
-                        let dur = utils::value_since(&x);
-                        value_stuff.set(tot_value_since(dur));
+                        sync_tasks(&state);
                     },
 
-                    if is_syncing() {
+                    if sync_status() == SyncStatus::Syncing {
 
                         { tooltip_image("hourglass.svg", "syncing in progress", 34, 0.4) }
 
@@ -75,13 +144,63 @@ pub fn Home() -> Element {
                 { tooltip_image("addnew.svg", "new task", 34, 0.4) }
 
             }
+        }
 
-            button {
-                class: "emoji-button",
-                onclick: move |_| {
-                    State::refresh();
-                },
-                { tooltip("🔄", "update", 0.4) }
+        SyncIndicator {}
+
+        if auth.read().is_authed() {
+            Link {
+                font_size: "0.8em",
+                to: Route::SyncPreview {},
+                "preview sync"
+            }
+        }
+
+        if let Some(warning) = skew_warning {
+            p {
+                color: "red",
+                "{warning}"
+            }
+        }
+
+        if pending_uploads() > 0 {
+            p {
+                font_size: "0.8em",
+                color: "gray",
+                if pending_uploads() == 1 {
+                    "1 change pending"
+                } else {
+                    "{pending_uploads} changes pending"
+                }
+            }
+        }
+
+        if !upload_failures.read().is_empty() {
+            details {
+                color: "red",
+                summary { "⚠️ {upload_failures.read().len()} changes couldn't be uploaded, they'll be retried" }
+                for (id, e) in upload_failures() {
+                    div {
+                        font_size: "0.8em",
+                        "{id}: {e}"
+                    }
+                }
+            }
+        }
+
+        if !conflicts.read().is_empty() {
+            p {
+                color: "red",
+                "⚠️ {conflicts.read().len()} tasks were changed on two devices at once. "
+                Link { to: Route::Conflicts {}, "pick what to keep" }
+            }
+        }
+
+        if has_quarantined {
+            p {
+                color: "red",
+                "⚠️ some saved data couldn't be read. "
+                Link { to: Route::Recovery {}, "recover it" }
             }
         }
 
@@ -112,36 +231,67 @@ pub fn Home() -> Element {
                     let s = e.value().clone();
                     log(("it moved lol: ", &s));
                     *selected_value.write() = s;
-                    State::refresh();
                 },
                 option { value: "1", "24h" },
                 option { value: "2", "7d" },
                 option { value: "3", "30d" },
                 option { value: "4", "all" },
             }
+
+            select {
+                margin_left: "10px",
+                class: "dropdown",
+                value: "{tick_rate}",
+                width: "70px",
+                onchange: move |e| {
+                    let s = e.value();
+                    cache::save_tick_rate(&s);
+                    tick_rate.set(s);
+                    ticker.restart();
+                },
+                option { value: "5", "5s" },
+                option { value: "60", "1m" },
+                option { value: "300", "5m" },
+                option { value: "off", "off" },
+            }
+        }
+
+        label {
+            "done at "
+            input {
+                r#type: "datetime-local",
+                value: when(),
+                max: utils::datetime_input_str(now()),
+                border_color: if when_error().is_some() { "red" } else { "lightskyblue" },
+                oninput: move |event| {
+                    when.set(event.value());
+                    when_error.set(None);
+                },
+            }
+        }
+        if let Some(e) = when_error() {
+            p { color: "red", font_size: "0.8em", "{e}" }
         }
 
         ul {
             padding: "0",
             margin: "0",
             list_style_type: "none",
-            display: "flex",
-            flex_direction: "column",
+            position: "relative",
+            height: "{list_height}px",
             max_height: "60vh",
             overflow_y: "auto",
 
-            li {
-                p {
-                    margin_top: "40px",
-                    ""
-                }
-            }
-
-            for task in tasks() {
+            for (rank, task) in rows {
                 li {
+                    key: "{task.id}",
                     display: "flex",
                     flex_direction: "row",
-                    margin_bottom: "10px",
+                    align_items: "center",
+                    position: "absolute",
+                    top: "{LIST_TOP + rank * ROW_HEIGHT}px",
+                    height: "{ROW_HEIGHT}px",
+                    transition: "top 0.6s ease",
 
                     div {
                         button {
@@ -151,11 +301,19 @@ pub fn Home() -> Element {
                             onclick: move |_| {
                                 log_to_console(&task.name);
                                 if task.disc {
-                                    Tasks::load_offline().do_task(task.id, 1.0);
+                                    let time = match utils::parse_when_input(&when()) {
+                                        Ok(time) => time,
+                                        Err(e) => {
+                                            when_error.set(Some(e));
+                                            return;
+                                        }
+                                    };
+                                    store.write().do_task(task.id, 1.0, time);
+                                    now.set(utils::current_time());
+                                    when.set(String::new());
                                 } else {
                                     navigator.replace(Route::Units{id: task.id});
                                 };
-                                State::refresh();
                             },
                             "✅"
                         }
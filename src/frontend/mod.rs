@@ -1,6 +1,7 @@
 #![allow(non_snake_case)]
 
 use crate::cache;
+use crate::outbox;
 use crate::task::{Contask, LogPriority, Task, Tasks, ValueEq};
 use crate::utils;
 use crate::State;
@@ -11,17 +12,35 @@ use wasm_bindgen::prelude::*;
 use web_sys::console;
 
 mod about;
+mod backups;
+mod conflicts;
+mod deleted;
 mod edit;
+mod history;
 mod home;
 mod new;
+mod preview;
+mod recovery;
+mod settings;
 mod stats;
+mod status;
+mod undo;
 mod units;
 
 use about::*;
+use backups::*;
+use conflicts::*;
+use deleted::*;
 use edit::*;
+use history::*;
 use home::*;
 use new::*;
+use preview::*;
+use recovery::*;
+use settings::*;
 use stats::*;
+use status::*;
+use undo::*;
 use units::*;
 
 pub fn App() -> Element {
@@ -89,10 +108,26 @@ pub enum Route {
     Editcont { id: Uuid },
     #[route("/stats/:id")]
     Stats { id: Uuid },
+    #[route("/history/:id")]
+    History { id: Uuid },
+    #[route("/deleted")]
+    Deleted {},
+    #[route("/recovery")]
+    Recovery {},
+    #[route("/backups")]
+    Backups {},
+    #[route("/sync")]
+    SyncPreview {},
+    #[route("/conflicts")]
+    Conflicts {},
+    #[route("/settings")]
+    Settings {},
 }
 
 #[component]
 fn Wrapper() -> Element {
+    use_auto_sync();
+
     rsx! {
         div {
             display: "flex",
@@ -114,6 +149,8 @@ fn Wrapper() -> Element {
                     { footer() }
                 }
             }
+
+            UndoToast {}
         }
     }
 }
@@ -125,6 +162,21 @@ pub fn footer() -> Element {
                 to: Route::About {},
                 "about"
             }
+            Link {
+                margin_left: "20px",
+                to: Route::Deleted {},
+                "deleted"
+            }
+            Link {
+                margin_left: "20px",
+                to: Route::Backups {},
+                "backups"
+            }
+            Link {
+                margin_left: "20px",
+                to: Route::Settings {},
+                "settings"
+            }
             a {
                 margin_left: "20px",
                 href: "https://github.com/tbs1996/firelog/issues",
@@ -145,6 +197,13 @@ impl Route {
             Self::Edit { .. } => true,
             Self::Editcont { .. } => true,
             Self::Stats { .. } => true,
+            Self::History { .. } => true,
+            Self::Deleted { .. } => false,
+            Self::Recovery { .. } => false,
+            Self::Backups { .. } => false,
+            Self::SyncPreview { .. } => false,
+            Self::Conflicts { .. } => false,
+            Self::Settings { .. } => false,
         }
     }
 }
@@ -259,17 +318,25 @@ pub struct TaskProp {
 }
 
 impl TaskProp {
-    fn from_task(task: &Task) -> Self {
+    fn from_task(task: &Task, now: Duration) -> Self {
         Self {
             name: task.metadata.name.clone(),
-            priority: utils::format_float(task.priority()),
+            priority: utils::format_float(task.priority(now)),
             id: task.id,
             disc: task.is_disc(),
-            value: utils::format_float(task.value()),
+            value: utils::format_float(task.value(now)),
         }
     }
 }
 
+/// A link target that downloads `raw` as a file.
+fn download_href(raw: &str) -> String {
+    format!(
+        "data:application/json;charset=utf-8,{}",
+        js_sys::encode_uri_component(raw)
+    )
+}
+
 fn back_str() -> &'static str {
     include_str!("../../assets/return.svg")
 }
@@ -278,28 +345,19 @@ fn delete_str() -> &'static str {
     include_str!("../../assets/delete.svg")
 }
 
-pub fn task_props() -> Vec<TaskProp> {
-    let mut tasks = Tasks::load_offline();
-    tasks.prune_deleted();
+pub fn task_props(tasks: &Tasks, now: Duration) -> Vec<TaskProp> {
+    let mut tasks: Vec<(f32, TaskProp)> = tasks
+        .active()
+        .map(|task| (task.priority(now), TaskProp::from_task(task, now)))
+        .collect();
 
-    let tasks = tasks.to_vec_sorted();
-
-    let tasks: Vec<TaskProp> = tasks.iter().map(|task| TaskProp::from_task(task)).collect();
-    tasks
+    tasks.sort_by(|a, b| b.0.total_cmp(&a.0));
+    tasks.into_iter().map(|(_, prop)| prop).collect()
 }
 
-pub fn tot_value_since(since: Duration) -> f32 {
-    let time = utils::current_time() - since;
-    let mut value = 0.;
-    let mut tasks = Tasks::load_offline();
-    tasks.prune_deleted();
-    let tasks = tasks.to_vec_sorted();
-
-    for task in tasks {
-        value += task.value_since(time);
-    }
-
-    value
+pub fn tot_value_since(tasks: &Tasks, since: Duration, now: Duration) -> f32 {
+    let time = now.saturating_sub(since);
+    tasks.active().map(|task| task.value_since(time)).sum()
 }
 
 pub enum TaskType {
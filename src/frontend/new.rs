@@ -2,14 +2,14 @@
 
 use super::*;
 
-use crate::firebase;
-use crate::task::{Task, Tasks};
+use crate::task::Task;
 use crate::State;
 
 #[component]
 pub fn New() -> Element {
     let state = use_context::<State>();
     let mut selected_value = state.inner.lock().unwrap().tasktype.clone();
+    let mut local_only = use_signal(|| false);
     let navigator = navigator();
 
     log("neww");
@@ -56,41 +56,44 @@ pub fn New() -> Element {
             }
         }
 
+        label {
+            display: "block",
+            input {
+                r#type: "checkbox",
+                checked: local_only(),
+                onchange: move |evt| local_only.set(evt.checked()),
+            }
+            "keep on this device only"
+        }
+
         if *selected_value.read() == "disc" {
-            { Disc() }
+            Disc { local_only }
         } else {
-            { Cont() }
+            Cont { local_only }
         }
     }
 }
 
 #[component]
-pub fn Disc() -> Element {
+pub fn Disc(local_only: Signal<bool>) -> Element {
     let state = use_context::<State>();
 
-    let auth = (*state.inner.lock().unwrap().auth_status.clone().read()).clone();
+    let store = state.inner.lock().unwrap().tasks;
 
     let navigator = navigator();
 
     log("neww");
 
     let closure = move |task: Option<Task>| {
-        let Some(task) = task else {
+        let Some(mut task) = task else {
             return;
         };
 
-        if let Some(user) = auth.user() {
-            let future = firebase::send_task_to_firestore(user.uid.clone(), &task);
-            wasm_bindgen_futures::spawn_local(async {
-                future.await.unwrap();
-            });
-        }
-
-        let mut the_tasks = Tasks::load_offline();
-        the_tasks.insert(task);
-        the_tasks.save_offline();
+        // Set before it's created, so that not even that is uploaded.
+        task.metadata.local_only = local_only();
+        let mut store = store;
+        store.write().create(task);
         navigator.replace(Route::Home {});
-        State::refresh();
     };
 
     rsx! {
@@ -99,31 +102,23 @@ pub fn Disc() -> Element {
 }
 
 #[component]
-pub fn Cont() -> Element {
+pub fn Cont(local_only: Signal<bool>) -> Element {
     let state = use_context::<State>();
 
-    let auth = (*state.inner.lock().unwrap().auth_status.clone().read()).clone();
+    let store = state.inner.lock().unwrap().tasks;
 
     let navigator = navigator();
 
     let closure = move |task: Option<Task>| {
-        let Some(task) = task else {
+        let Some(mut task) = task else {
             return;
         };
 
+        task.metadata.local_only = local_only();
         log_to_console(&task);
-        if let Some(user) = auth.user() {
-            let future = firebase::send_task_to_firestore(user.uid.clone(), &task);
-            wasm_bindgen_futures::spawn_local(async {
-                future.await.unwrap();
-            });
-        }
-
-        let mut the_tasks = Tasks::load_offline();
-        the_tasks.insert(task);
-        the_tasks.save_offline();
+        let mut store = store;
+        store.write().create(task);
         navigator.replace(Route::Home {});
-        State::refresh();
     };
 
     rsx! {
@@ -0,0 +1,100 @@
+#![allow(non_snake_case)]
+
+use super::*;
+
+use crate::sync::{self, Fetched, SyncStatus};
+use crate::State;
+
+/// What a sync would do, fetched but not applied until the user says so.
+#[component]
+pub fn SyncPreview() -> Element {
+    let state = use_context::<State>();
+    let store = state.inner.lock().unwrap().tasks;
+    let status = state.inner.lock().unwrap().sync_status;
+    let navigator = use_navigator();
+    let mut fetched = use_signal(|| None::<Result<Fetched, String>>);
+    let mut not_started = use_signal(|| false);
+
+    let fetching = state.clone();
+    use_hook(move || {
+        spawn(async move {
+            let res = match sync::backend(&fetching) {
+                Some(backend) => sync::fetch_for(&fetching, &backend).await,
+                None => Err("not signed in".to_string()),
+            };
+            fetched.set(Some(res));
+        });
+    });
+
+    let fetched = match fetched() {
+        None => {
+            return rsx! {
+                Link {to: Route::Home{}, "back"}
+                p { "checking the server…" }
+            }
+        }
+        Some(Err(e)) => {
+            return rsx! {
+                Link {to: Route::Home{}, "back"}
+                p { color: "red", "couldn't check the server: {e}" }
+            }
+        }
+        Some(Ok(fetched)) => fetched,
+    };
+    let previews = fetched.preview(&store.read());
+
+    rsx! {
+        Link {to: Route::Home{}, "back"}
+
+        if previews.is_empty() {
+            p { "already in sync" }
+        }
+
+        ul {
+            padding: "0",
+            list_style_type: "none",
+
+            for preview in previews {
+                li {
+                    margin_bottom: "15px",
+
+                    div { "{preview.name}" }
+                    div {
+                        color: "#666",
+                        font_size: "0.8em",
+                        "{preview.download} to bring down, {preview.upload} to send up"
+                    }
+                    for change in preview.changes {
+                        div {
+                            font_size: "0.8em",
+                            "{change}"
+                        }
+                    }
+                }
+            }
+        }
+
+        if not_started() {
+            p { color: "red", "couldn't sync, check that you're online and signed in" }
+        }
+
+        button {
+            disabled: matches!(status(), SyncStatus::Syncing | SyncStatus::Offline),
+            onclick: move |_| {
+                if sync::apply_fetched(&state, fetched.clone()) {
+                    navigator.replace(Route::Home {});
+                } else {
+                    not_started.set(true);
+                }
+            },
+            "apply"
+        }
+        button {
+            margin_left: "10px",
+            onclick: move |_| {
+                navigator.replace(Route::Home {});
+            },
+            "cancel"
+        }
+    }
+}
@@ -0,0 +1,87 @@
+#![allow(non_snake_case)]
+
+use super::*;
+
+use crate::journal::Journal;
+use crate::State;
+
+/// Data that couldn't be read is kept aside until the user has dealt with it here.
+#[component]
+pub fn Recovery() -> Element {
+    let state = use_context::<State>();
+    let mut store = state.inner.lock().unwrap().tasks;
+    let mut quarantined = use_signal(cache::quarantined);
+    let mut message = use_signal(String::new);
+
+    rsx! {
+        Link {to: Route::Home{}, "back"}
+
+        p {
+            "Some saved data couldn't be read. It's been set aside so that nothing overwrites it."
+        }
+
+        Link { to: Route::Backups {}, "restore from backup" }
+
+        if !message().is_empty() {
+            p { "{message}" }
+        }
+
+        if quarantined().is_empty() {
+            p { "nothing to recover" }
+        }
+
+        ul {
+            padding: "0",
+            list_style_type: "none",
+
+            for entry in quarantined() {
+                li {
+                    key: "{entry.key}",
+                    margin_bottom: "20px",
+
+                    div { "{entry.source}, {entry.raw.len()} bytes" }
+
+                    a {
+                        href: download_href(&entry.raw),
+                        download: "firelog-{entry.key}.json",
+                        "export raw"
+                    }
+                    button {
+                        margin_left: "10px",
+                        onclick: {
+                            let raw = entry.raw.clone();
+                            move |_| {
+                                let ops = Journal::salvage(&raw);
+                                let count = ops.len();
+                                store.write().recover(ops);
+                                message.set(format!("repaired {} changes", count));
+                            }
+                        },
+                        "attempt repair"
+                    }
+                    button {
+                        margin_left: "10px",
+                        onclick: {
+                            let key = entry.key.clone();
+                            move |_| {
+                                let confirmed = web_sys::window()
+                                    .and_then(|window| {
+                                        window
+                                            .confirm_with_message("Discard this data for good? Export it first if you might need it.")
+                                            .ok()
+                                    })
+                                    .unwrap_or(false);
+
+                                if confirmed {
+                                    cache::discard_quarantined(&key);
+                                    quarantined.set(cache::quarantined());
+                                }
+                            }
+                        },
+                        "discard"
+                    }
+                }
+            }
+        }
+    }
+}
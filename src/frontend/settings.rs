@@ -0,0 +1,85 @@
+#![allow(non_snake_case)]
+
+use super::*;
+
+use crate::backend::{SelfHosted, SyncBackend};
+use crate::State;
+
+/// Where to sync: Firestore by default, or a server of our own, see `server/README.md`.
+#[component]
+pub fn Settings() -> Element {
+    let state = use_context::<State>();
+    let mut auth = state.inner.lock().unwrap().auth_status;
+    let mut server = use_signal(cache::sync_server);
+    let mut url = use_signal(String::new);
+    let mut token = use_signal(String::new);
+    let mut connecting = use_signal(|| false);
+    let mut error = use_signal(|| None::<String>);
+
+    let connect = move |_| {
+        connecting.set(true);
+        error.set(None);
+        spawn(async move {
+            match SelfHosted::connect(&url(), &token()).await {
+                Ok(config) => {
+                    cache::save_sync_server(Some(&config));
+                    let uid = SelfHosted::new(config.clone()).uid().to_string();
+                    server.set(Some(config));
+                    // Starts syncing with it, see `use_auto_sync`.
+                    auth.set(AuthStatus::Auth(AuthUser { uid }));
+                }
+                Err(e) => error.set(Some(e)),
+            }
+            connecting.set(false);
+        });
+    };
+
+    rsx! {
+        Link {to: Route::Home{}, "back"}
+
+        h3 { "sync server" }
+
+        if let Some(config) = server() {
+            p { "syncing with {config.url} as {config.user}" }
+            button {
+                onclick: move |_| {
+                    cache::save_sync_server(None);
+                    server.set(None);
+                    // Sign in again to sync with Firestore.
+                    auth.set(AuthStatus::Nope);
+                },
+                "disconnect"
+            }
+        } else {
+            p {
+                font_size: "0.8em",
+                color: "#666",
+                "Sync with a server of your own instead of Firestore. Run firelog-server and make a token with it."
+            }
+            input {
+                r#type: "url",
+                placeholder: "http://127.0.0.1:8787",
+                value: url(),
+                width: "100%",
+                oninput: move |event| url.set(event.value()),
+            }
+            input {
+                r#type: "password",
+                placeholder: "token",
+                value: token(),
+                width: "100%",
+                margin_top: "5px",
+                oninput: move |event| token.set(event.value()),
+            }
+            button {
+                margin_top: "10px",
+                disabled: connecting() || url().trim().is_empty() || token().trim().is_empty(),
+                onclick: connect,
+                if connecting() { "connecting…" } else { "connect" }
+            }
+            if let Some(e) = error() {
+                p { color: "red", "couldn't connect: {e}" }
+            }
+        }
+    }
+}
@@ -2,13 +2,21 @@
 
 use super::*;
 
-use crate::task::Tasks;
 use crate::utils;
+use crate::State;
 use uuid::Uuid;
 
 #[component]
 pub fn Stats(id: Uuid) -> Element {
-    let task = Tasks::load_offline().get_task(id).unwrap();
+    let state = use_context::<State>();
+    let task = state
+        .inner
+        .lock()
+        .unwrap()
+        .tasks
+        .read()
+        .get_task(id)
+        .unwrap();
     let mut stats: Vec<(String, String)> = vec![];
 
     stats.push(("meta".to_string(), format!("{:?}", &task.metadata)));
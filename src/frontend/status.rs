@@ -0,0 +1,104 @@
+#![allow(non_snake_case)]
+
+use super::*;
+
+use crate::backend::Subscription;
+use crate::sync::{subscribe, sync_tasks, SyncStatus};
+use gloo::events::EventListener;
+use gloo::timers::future::TimeoutFuture;
+use gloo::utils::{document, window};
+use std::rc::Rc;
+
+// How often to sync while the page is open and visible.
+const SYNC_INTERVAL_MILLIS: u32 = 5 * 60 * 1000;
+
+/// Syncs when signed in, every few minutes, and when the browser comes back online. Operations
+/// from other devices are merged as they come in between.
+pub fn use_auto_sync() {
+    let state = use_context::<State>();
+    let auth = state.inner.lock().unwrap().auth_status;
+    let mut status = state.inner.lock().unwrap().sync_status;
+    let mut last_synced = state.inner.lock().unwrap().last_synced;
+    let mut live = use_signal(|| None::<Subscription>);
+
+    let signed_in = state.clone();
+    use_effect(move || {
+        live.set(None);
+        let Some(user) = auth.read().user() else {
+            return;
+        };
+
+        last_synced.set(cache::last_synced(&user.uid));
+        // Sends what was queued while signed out, or before the page was last closed.
+        outbox::flush(&signed_in);
+        sync_tasks(&signed_in);
+    });
+
+    // Listens from where the last sync got to, so only once there's been one.
+    let listening = state.clone();
+    use_effect(move || {
+        let synced = last_synced.read().is_some();
+        if synced && auth.read().user().is_some() && live.peek().is_none() {
+            live.set(subscribe(&listening));
+        }
+    });
+
+    let interval = state.clone();
+    use_future(move || {
+        let state = interval.clone();
+        async move {
+            loop {
+                TimeoutFuture::new(SYNC_INTERVAL_MILLIS).await;
+                if !document().hidden() {
+                    sync_tasks(&state);
+                }
+            }
+        }
+    });
+
+    use_hook(move || {
+        if !utils::is_online() {
+            status.set(SyncStatus::Offline);
+        }
+
+        let online = EventListener::new(&window(), "online", move |_| {
+            status.set(SyncStatus::Idle);
+            outbox::flush(&state);
+            sync_tasks(&state);
+        });
+        let offline = EventListener::new(&window(), "offline", move |_| {
+            status.set(SyncStatus::Offline);
+        });
+        Rc::new((online, offline))
+    });
+}
+
+/// A line on how syncing is going.
+#[component]
+pub fn SyncIndicator() -> Element {
+    let state = use_context::<State>();
+    let status = state.inner.lock().unwrap().sync_status;
+    let last_synced = state.inner.lock().unwrap().last_synced;
+
+    let (text, color) = match status() {
+        SyncStatus::Syncing => ("syncing…".to_string(), "gray"),
+        SyncStatus::Offline => (
+            "📴 offline, changes are kept on this device".to_string(),
+            "gray",
+        ),
+        SyncStatus::Error(e) => (format!("⚠️ sync failed: {}", e), "red"),
+        SyncStatus::Idle => {
+            let last_synced = last_synced()?;
+            let ago = utils::current_time().saturating_sub(last_synced);
+            (format!("synced {} ago", utils::dur_format(ago)), "gray")
+        }
+    };
+
+    rsx! {
+        p {
+            font_size: "0.8em",
+            color: "{color}",
+            "{text}"
+        }
+    }
+}
@@ -0,0 +1,110 @@
+#![allow(non_snake_case)]
+
+use super::*;
+
+use crate::undo::Event;
+use gloo::events::{EventListener, EventListenerOptions};
+use gloo::timers::future::TimeoutFuture;
+use gloo::utils::document;
+use std::rc::Rc;
+use wasm_bindgen::JsCast;
+
+// How long the toast stays up after an action.
+const TOAST_MILLIS: u32 = 5000;
+
+/// Ctrl+Z undoes, Ctrl+Shift+Z and Ctrl+Y redo. Cmd works too. Text fields keep their own undo.
+fn use_undo_shortcuts(mut store: Signal<Tasks>) {
+    use_hook(move || {
+        Rc::new(EventListener::new_with_options(
+            &document(),
+            "keydown",
+            EventListenerOptions::enable_prevent_default(),
+            move |event| {
+                let Some(event) = event.dyn_ref::<web_sys::KeyboardEvent>() else {
+                    return;
+                };
+
+                if !(event.ctrl_key() || event.meta_key()) {
+                    return;
+                }
+
+                let editing_text = document()
+                    .active_element()
+                    .is_some_and(|el| matches!(el.tag_name().as_str(), "INPUT" | "TEXTAREA"));
+                if editing_text {
+                    return;
+                }
+
+                match event.key().to_lowercase().as_str() {
+                    "z" if event.shift_key() => store.write().redo(),
+                    "z" => store.write().undo(),
+                    "y" => store.write().redo(),
+                    _ => return,
+                }
+                event.prevent_default();
+            },
+        ))
+    });
+}
+
+#[component]
+pub fn UndoToast() -> Element {
+    let state = use_context::<State>();
+    let mut store = state.inner.lock().unwrap().tasks;
+    use_undo_shortcuts(store);
+
+    let notice = use_memo(move || store.read().history().notice().cloned());
+    let mut hidden = use_signal(|| None::<u64>);
+
+    use_effect(move || {
+        let Some(seq) = notice().map(|notice| notice.seq) else {
+            return;
+        };
+
+        spawn(async move {
+            TimeoutFuture::new(TOAST_MILLIS).await;
+            if hidden.peek().is_none_or(|hidden| hidden < seq) {
+                hidden.set(Some(seq));
+            }
+        });
+    });
+
+    let notice = notice()?;
+
+    if hidden().is_some_and(|hidden| hidden >= notice.seq) {
+        return None;
+    }
+
+    let (text, button) = match notice.event {
+        Event::Done | Event::Redone => (notice.label.clone(), "Undone? ↶"),
+        Event::Undone => (format!("undid: {}", notice.label), "redo ↷"),
+    };
+
+    rsx! {
+        div {
+            position: "fixed",
+            bottom: "20px",
+            left: "50%",
+            transform: "translateX(-50%)",
+            display: "flex",
+            align_items: "center",
+            padding: "8px 12px",
+            border_radius: "8px",
+            background_color: "#333",
+            color: "white",
+            z_index: "10",
+
+            span { "{text}" }
+            button {
+                margin_left: "10px",
+                onclick: move |_| {
+                    match notice.event {
+                        Event::Done | Event::Redone => store.write().undo(),
+                        Event::Undone => store.write().redo(),
+                    }
+                },
+                "{button}"
+            }
+        }
+    }
+}
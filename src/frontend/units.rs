@@ -2,7 +2,7 @@
 
 use super::*;
 
-use crate::task::Tasks;
+use crate::utils;
 use crate::State;
 use uuid::Uuid;
 
@@ -12,10 +12,13 @@ fn back_str() -> &'static str {
 
 #[component]
 pub fn Units(id: Uuid) -> Element {
-    let mut task = Tasks::load_offline().get_task(id).unwrap();
-    let unit_name = task.unit_name();
+    let state = use_context::<State>();
+    let mut store = state.inner.lock().unwrap().tasks;
+    let unit_name = store.read().get_task(id).unwrap().unit_name();
 
     let mut input = Signal::new(String::new());
+    let mut when = Signal::new(String::new());
+    let mut when_error = use_signal(|| None::<&'static str>);
 
     let navigator = navigator();
 
@@ -56,9 +59,15 @@ pub fn Units(id: Uuid) -> Element {
             onsubmit: move |event| {
                 let data = event.data().values();
                 let units: f32 = data.get("input").unwrap().as_value().to_string().parse().unwrap();
-                task.do_task(units);
+                let time = match utils::parse_when_input(&data.get("when").unwrap().as_value()) {
+                    Ok(time) => time,
+                    Err(e) => {
+                        when_error.set(Some(e));
+                        return;
+                    }
+                };
+                store.write().do_task(id, units, time);
                 navigator.replace(Route::Home {});
-                State::refresh();
             },
 
             div {
@@ -76,6 +85,20 @@ pub fn Units(id: Uuid) -> Element {
                     height: "34px",
                     text_align: "center",
                 },
+                input {
+                    r#type: "datetime-local",
+                    value: when(),
+                    name: "when",
+                    max: utils::datetime_input_str(utils::current_time()),
+                    oninput: move |event| {
+                        when.set(event.value());
+                        when_error.set(None);
+                    },
+                    width: "170px",
+                    height: "34px",
+                    margin_left: "8px",
+                    text_align: "center",
+                },
                 button {
                     r#type: "submit",
                     class: "confirm",
@@ -85,5 +108,9 @@ pub fn Units(id: Uuid) -> Element {
                 },
             }
         }
+
+        if let Some(e) = when_error() {
+            p { color: "red", text_align: "center", "{e}" }
+        }
     }
 }
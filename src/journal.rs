@@ -0,0 +1,526 @@
+use crate::log;
+use crate::schema::{self, Unreadable};
+use crate::task::{Field, LogRecord, MetaData, Task, TaskID, TaskLog, Version};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use uuid::Uuid;
+
+type UnixTime = Duration;
+
+/// Something that was done to a task.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum OpKind {
+    Created(Box<MetaData>),
+    Edited(Field),
+    Logged(LogRecord),
+    LogDeleted(Uuid),
+    Deleted,
+    Restored,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Op {
+    pub id: Uuid,
+    pub task: TaskID,
+    pub version: Version,
+    pub kind: OpKind,
+}
+
+impl Op {
+    pub fn new(task: TaskID, version: Version, kind: OpKind) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            task,
+            version,
+            kind,
+        }
+    }
+
+    /// Every field and record keeps whichever change to it is newest, so operations can be
+    /// applied in any order once the task exists.
+    fn apply(&self, tasks: &mut HashMap<TaskID, Task>) {
+        if let OpKind::Created(metadata) = &self.kind {
+            match tasks.get_mut(&self.task) {
+                Some(task) => task.metadata.merge(*metadata.clone()),
+                None => {
+                    tasks.insert(
+                        self.task,
+                        Task {
+                            id: self.task,
+                            log: TaskLog::default(),
+                            metadata: *metadata.clone(),
+                        },
+                    );
+                }
+            }
+            return;
+        }
+
+        let Some(task) = tasks.get_mut(&self.task) else {
+            log(("operation on unknown task: ", self));
+            return;
+        };
+
+        match &self.kind {
+            OpKind::Created(_) => unreachable!(),
+            OpKind::Edited(field) => task.metadata.apply(field.clone(), self.version),
+            OpKind::Logged(record) => task.log.upsert(record.clone()),
+            OpKind::LogDeleted(id) => task.log.tombstone(*id, self.version.clock),
+            OpKind::Deleted => task.metadata.apply(Field::Deleted(true), self.version),
+            OpKind::Restored => task.metadata.apply(Field::Deleted(false), self.version),
+        }
+    }
+
+    /// Also returns when the server stored the newest of them.
+    pub fn from_jsvalue(val: wasm_bindgen::JsValue) -> (Vec<Self>, Option<UnixTime>) {
+        let x: Vec<serde_json::Value> = serde_wasm_bindgen::from_value(val).unwrap();
+        Self::from_docs(&x)
+    }
+
+    /// The same, from documents already read as JSON.
+    pub fn from_docs(docs: &[serde_json::Value]) -> (Vec<Self>, Option<UnixTime>) {
+        let mut ops = vec![];
+        let mut newest = None;
+        for y in docs {
+            if let Some(synced_at) = y.get("synced_at").and_then(|t| t.as_f64()) {
+                newest = newest.max(Some(UnixTime::from_millis(synced_at as u64)));
+            }
+
+            let version = y.get("v").and_then(|v| v.as_u64()).unwrap_or_default();
+            let op = y
+                .get("op")
+                .and_then(|op| op.as_str())
+                .ok_or_else(|| Unreadable::Corrupt("no operation".to_string()))
+                .and_then(|op| {
+                    serde_json::from_str(op).map_err(|e| Unreadable::Corrupt(e.to_string()))
+                })
+                .and_then(|op| schema::OP.load_at(version as u32, op));
+
+            // They stay on the server either way, for devices that can read them.
+            match op {
+                Ok(op) => ops.push(op),
+                Err(e) => {
+                    log(("skipping operation: ", y.get("id"), e.to_string()));
+                }
+            }
+        }
+
+        (ops, newest)
+    }
+}
+
+/// Applies the operations, creations first so that nothing is dropped for arriving before the
+/// task it belongs to.
+pub fn apply_all(tasks: &mut HashMap<TaskID, Task>, ops: &[Op]) {
+    let (created, rest): (Vec<&Op>, Vec<&Op>) = ops
+        .iter()
+        .partition(|op| matches!(op.kind, OpKind::Created(_)));
+
+    for op in created.into_iter().chain(rest) {
+        op.apply(tasks);
+    }
+}
+
+/// Everything that's been done to the tasks, from every device. The tasks themselves are
+/// derived from it, and syncing is a matter of exchanging the operations the other side lacks.
+#[derive(Debug, Clone, Default)]
+pub struct Journal {
+    ops: Vec<Op>,
+    ids: HashSet<Uuid>,
+    // Stored operations we can't read, kept as they are so that they're saved back.
+    unreadable: Vec<serde_json::Value>,
+}
+
+impl From<Vec<Op>> for Journal {
+    fn from(ops: Vec<Op>) -> Self {
+        let mut journal = Self::default();
+        for op in ops {
+            journal.insert(op);
+        }
+        journal
+    }
+}
+
+impl Journal {
+    pub fn to_json(&self) -> String {
+        let mut entries: Vec<serde_json::Value> =
+            self.ops.iter().map(|op| schema::OP.wrap(op)).collect();
+        entries.extend(self.unreadable.iter().cloned());
+        schema::JOURNAL.wrap(&entries).to_string()
+    }
+
+    /// Operations that can't be read are kept, the journal itself has to be readable.
+    pub fn from_json(s: &str) -> Result<Self, Unreadable> {
+        let entries: Vec<serde_json::Value> = schema::JOURNAL.parse(s)?;
+
+        let mut journal = Self::default();
+        for entry in entries {
+            match schema::OP.load(entry.clone()) {
+                Ok(op) => {
+                    journal.insert(op);
+                }
+                Err(_) => journal.unreadable.push(entry),
+            }
+        }
+
+        Ok(journal)
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// Every operation that can be read from a damaged journal, e.g. one that was cut off while
+    /// being written.
+    pub fn salvage(raw: &str) -> Vec<Op> {
+        let mut ops = vec![];
+        let mut ids = HashSet::new();
+        let mut start = 0;
+
+        while let Some(offset) = raw[start..].find('{') {
+            let at = start + offset;
+            let mut values =
+                serde_json::Deserializer::from_str(&raw[at..]).into_iter::<serde_json::Value>();
+
+            if let Some(Ok(val)) = values.next() {
+                if let Ok(op) = schema::OP.load::<Op>(val) {
+                    if ids.insert(op.id) {
+                        ops.push(op);
+                    }
+                    start = at + values.byte_offset();
+                    continue;
+                }
+            }
+
+            // Not an operation, but there may be some inside it.
+            start = at + 1;
+        }
+
+        ops
+    }
+
+    /// How many stored operations couldn't be read.
+    pub fn unreadable(&self) -> usize {
+        self.unreadable.len()
+    }
+
+    pub fn contains(&self, id: Uuid) -> bool {
+        self.ids.contains(&id)
+    }
+
+    /// Adds the operation unless we already have it. Returns whether it was added.
+    pub fn insert(&mut self, op: Op) -> bool {
+        if !self.ids.insert(op.id) {
+            return false;
+        }
+
+        self.ops.push(op);
+        true
+    }
+
+    pub fn replay(&self) -> HashMap<TaskID, Task> {
+        let mut tasks = HashMap::default();
+        apply_all(&mut tasks, &self.ops);
+        tasks
+    }
+
+    /// How the task's definition looked after each time it was saved, oldest first. Fields
+    /// edited in the same save share a version.
+    pub fn versions(&self, task: TaskID) -> Vec<(Version, MetaData)> {
+        let mut ops: Vec<&Op> = self
+            .ops
+            .iter()
+            .filter(|op| op.task == task)
+            .filter(|op| matches!(op.kind, OpKind::Created(_) | OpKind::Edited(_)))
+            .collect();
+        ops.sort_by_key(|op| op.version);
+
+        let mut versions: Vec<(Version, MetaData)> = vec![];
+        let mut current: Option<MetaData> = None;
+
+        for op in ops {
+            match (&op.kind, &mut current) {
+                (OpKind::Created(metadata), Some(current)) => current.merge(*metadata.clone()),
+                (OpKind::Created(metadata), None) => current = Some(*metadata.clone()),
+                (OpKind::Edited(field), Some(current)) => current.apply(field.clone(), op.version),
+                _ => continue,
+            }
+
+            let metadata = current.clone().unwrap();
+            match versions.last_mut() {
+                Some((version, last)) if *version == op.version => *last = metadata,
+                Some((_, last)) if metadata.changes(last).is_empty() => {}
+                _ => versions.push((op.version, metadata)),
+            }
+        }
+
+        versions
+    }
+
+    /// Operations that recreate tasks stored before there was a journal. The ids are derived from
+    /// the data, so devices migrating the same tasks come up with the same operations.
+    pub fn migrate(tasks: &HashMap<TaskID, Task>) -> Vec<Op> {
+        let mut ops = vec![];
+
+        for (id, task) in tasks {
+            let mut metadata = task.metadata.clone();
+            metadata.assign_legacy_versions();
+            let newest = metadata.versions.newest();
+            ops.push(Op {
+                id: derived_id(*id, newest),
+                task: *id,
+                version: Version::new(metadata.created.into(), Uuid::nil()),
+                kind: OpKind::Created(Box::new(metadata)),
+            });
+
+            for record in task.log.all() {
+                let version = Version::new(record.clock(), Uuid::nil());
+                ops.push(Op {
+                    id: derived_id(record.id, version),
+                    task: *id,
+                    version,
+                    kind: OpKind::Logged(record.clone()),
+                });
+            }
+        }
+
+        ops
+    }
+}
+
+fn derived_id(base: Uuid, version: Version) -> Uuid {
+    let clock = ((version.clock.time.as_millis()) << 32) | version.clock.counter as u128;
+    let salt =
+        (clock ^ version.device.as_u128()).wrapping_mul(0x9e37_79b9_7f4a_7c15_f39c_c060_5ced_c835);
+    Uuid::from_u128(base.as_u128().rotate_left(64) ^ salt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TASK: Uuid = Uuid::from_u128(7);
+    const PHONE: Uuid = Uuid::from_u128(1);
+    const LAPTOP: Uuid = Uuid::from_u128(2);
+
+    fn version(secs: u64, device: Uuid) -> Version {
+        Version::new(UnixTime::from_secs(secs).into(), device)
+    }
+
+    fn op(secs: u64, device: Uuid, kind: OpKind) -> Op {
+        Op::new(TASK, version(secs, device), kind)
+    }
+
+    fn created() -> Op {
+        let version = version(100, PHONE);
+        let metadata = MetaData::test("dishes", version);
+        Op::new(TASK, version, OpKind::Created(Box::new(metadata)))
+    }
+
+    fn record(secs: u64) -> LogRecord {
+        LogRecord {
+            id: Uuid::new_v4(),
+            time: UnixTime::from_secs(secs),
+            units: 1.,
+            updated: UnixTime::from_secs(secs),
+            ..Default::default()
+        }
+    }
+
+    fn replay(ops: &[Op]) -> HashMap<TaskID, Task> {
+        Journal::from(ops.to_vec()).replay()
+    }
+
+    #[test]
+    fn test_order_doesnt_matter() {
+        let ops = vec![
+            created(),
+            op(
+                200,
+                PHONE,
+                OpKind::Edited(Field::Name("do the dishes".into())),
+            ),
+            op(
+                300,
+                LAPTOP,
+                OpKind::Edited(Field::Length(Duration::from_secs(60))),
+            ),
+            op(150, LAPTOP, OpKind::Logged(record(150))),
+            op(400, PHONE, OpKind::Deleted),
+        ];
+
+        let mut reversed = ops.clone();
+        reversed.reverse();
+
+        for tasks in [replay(&ops), replay(&reversed)] {
+            let task = &tasks[&TASK];
+            assert_eq!(task.metadata.name, "do the dishes");
+            assert_eq!(task.metadata.length, Duration::from_secs(60));
+            assert!(task.metadata.deleted);
+            assert_eq!(task.log.records().count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_deleted_log_survives_exchange() {
+        let logged = record(150);
+        let on_phone = Journal::from(vec![
+            created(),
+            op(150, PHONE, OpKind::Logged(logged.clone())),
+        ]);
+
+        let mut on_laptop = on_phone.clone();
+        on_laptop.insert(op(200, LAPTOP, OpKind::LogDeleted(logged.id)));
+
+        let missing: Vec<Op> = on_laptop
+            .ops()
+            .iter()
+            .filter(|op| !on_phone.ops().contains(op))
+            .cloned()
+            .collect();
+        assert_eq!(missing.len(), 1);
+
+        let mut tasks = on_phone.replay();
+        apply_all(&mut tasks, &missing);
+        assert_eq!(tasks[&TASK].log.records().count(), 0);
+
+        // Getting the original log again doesn't bring it back.
+        apply_all(&mut tasks, &on_phone.ops()[1..]);
+        assert_eq!(tasks[&TASK].log.records().count(), 0);
+    }
+
+    #[test]
+    fn test_restore_beats_delete() {
+        let ops = vec![
+            created(),
+            op(200, PHONE, OpKind::Deleted),
+            op(300, LAPTOP, OpKind::Restored),
+        ];
+        assert!(!replay(&ops)[&TASK].metadata.deleted);
+    }
+
+    #[test]
+    fn test_versions() {
+        let raised = version(200, PHONE);
+        let ops = vec![
+            op(300, LAPTOP, OpKind::Edited(Field::Name("plates".into()))),
+            created(),
+            Op::new(
+                TASK,
+                raised,
+                OpKind::Edited(Field::Name("do the dishes".into())),
+            ),
+            Op::new(
+                TASK,
+                raised,
+                OpKind::Edited(Field::Length(Duration::from_secs(60))),
+            ),
+            op(250, PHONE, OpKind::Deleted),
+            // Doesn't change anything, so it's not a version of its own.
+            op(
+                260,
+                PHONE,
+                OpKind::Edited(Field::Length(Duration::from_secs(60))),
+            ),
+        ];
+
+        let versions = Journal::from(ops).versions(TASK);
+        let names: Vec<&str> = versions.iter().map(|(_, m)| m.name.as_str()).collect();
+        assert_eq!(names, ["dishes", "do the dishes", "plates"]);
+
+        let (version, metadata) = &versions[1];
+        assert_eq!(*version, raised);
+        assert_eq!(metadata.length, Duration::from_secs(60));
+        assert_eq!(versions[2].0.device, LAPTOP);
+    }
+
+    #[test]
+    fn test_storage() {
+        let journal = Journal::from(vec![created(), op(150, PHONE, OpKind::Logged(record(150)))]);
+        let json = journal.to_json();
+        assert_eq!(Journal::from_json(&json).unwrap().ops(), journal.ops());
+
+        // Stored before there were versions.
+        let bare = serde_json::to_string(journal.ops()).unwrap();
+        assert_eq!(Journal::from_json(&bare).unwrap().ops(), journal.ops());
+
+        // An operation from a newer version survives being loaded and saved again.
+        let newer = serde_json::json!({"v": 99, "data": {"kind": "Archived"}});
+        let mut entries: Vec<serde_json::Value> = serde_json::from_str::<serde_json::Value>(&json)
+            .unwrap()["data"]
+            .as_array()
+            .unwrap()
+            .clone();
+        entries.push(newer.clone());
+        let json = schema::JOURNAL.wrap(&entries).to_string();
+
+        let loaded = Journal::from_json(&json).unwrap();
+        assert_eq!(loaded.ops().len(), 2);
+        assert!(loaded.to_json().contains(&newer.to_string()));
+    }
+
+    #[test]
+    fn test_salvage() {
+        let journal = Journal::from(vec![
+            created(),
+            op(150, PHONE, OpKind::Logged(record(150))),
+            op(200, PHONE, OpKind::Deleted),
+        ]);
+        let json = journal.to_json();
+
+        // Cut off in the middle of the last operation.
+        let cut = &json[..json.len() - 30];
+        assert!(Journal::from_json(cut).is_err());
+        assert_eq!(Journal::salvage(cut), journal.ops()[..2]);
+
+        // Garbage in the middle of the second one.
+        let second = json.find("Logged").unwrap();
+        let damaged = format!("{}#{}", &json[..second], &json[second..]);
+        let salvaged = Journal::salvage(&damaged);
+        assert_eq!(
+            salvaged,
+            [journal.ops()[0].clone(), journal.ops()[2].clone()]
+        );
+    }
+
+    #[test]
+    fn test_migration() {
+        let mut tasks = replay(&[created()]);
+        let task = tasks.get_mut(&TASK).unwrap();
+        task.log.upsert(record(150));
+
+        let ops = Journal::migrate(&tasks);
+        assert_eq!(ops, Journal::migrate(&tasks));
+
+        let migrated = replay(&ops);
+        assert_eq!(migrated[&TASK].metadata, tasks[&TASK].metadata);
+        assert_eq!(migrated[&TASK].log.records().count(), 1);
+
+        // A device that has edited the task since comes up with a different operation.
+        let task = tasks.get_mut(&TASK).unwrap();
+        task.metadata
+            .apply(Field::Name("plates".into()), version(500, LAPTOP));
+        let edited = Journal::migrate(&tasks);
+        assert_ne!(edited[0].id, ops[0].id);
+        assert_eq!(edited[1].id, ops[1].id);
+    }
+
+    #[test]
+    fn test_from_docs() {
+        let (first, second) = (created(), op(200, LAPTOP, OpKind::Deleted));
+        let doc = |op: &Op, synced_at: u64| {
+            serde_json::json!({
+                "id": op.id,
+                "task_id": op.task,
+                "op": serde_json::to_string(op).unwrap(),
+                "v": schema::OP.version(),
+                "synced_at": synced_at,
+            })
+        };
+
+        let (ops, newest) = Op::from_docs(&[doc(&first, 5000), doc(&second, 3000)]);
+        assert_eq!(ops, [first, second]);
+        assert_eq!(newest, Some(UnixTime::from_millis(5000)));
+    }
+}
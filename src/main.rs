@@ -1,20 +1,29 @@
+use crate::backend::SyncBackend;
 use crate::task::MetaData;
 use dioxus::prelude::*;
+use futures::executor::block_on;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
 use tracing::Level;
 
+mod backend;
+mod backup;
 mod cache;
+mod clock;
+mod conflict;
+mod earnings;
 mod firebase;
 mod frontend;
+mod journal;
+mod outbox;
+mod schema;
 mod sync;
 mod task;
+mod undo;
 mod utils;
 
 use crate::frontend::App;
-use crate::frontend::TaskProp;
 use crate::frontend::*;
-use crate::task::Task;
+use crate::task::Tasks;
 
 fn main() {
     dioxus_logger::init(Level::INFO).expect("failed to init logger");
@@ -35,21 +44,9 @@ impl State {
     }
 
     fn auth_user(&self) -> Option<AuthUser> {
-        let state = use_context::<State>();
-        let x = (*state.inner.lock().unwrap().auth_status.read()).clone();
+        let x = (*self.inner.lock().unwrap().auth_status.peek()).clone();
         x.user()
     }
-
-    fn refresh() {
-        let state = use_context::<State>();
-        let mut tasks = state.inner.lock().unwrap().tasks.clone();
-        let mut value_stuff = state.inner.lock().unwrap().value_stuff.clone();
-        let selected_value = state.inner.lock().unwrap().selected_dur.clone();
-        let x = selected_value.read();
-        let dur = utils::value_since(&x);
-        tasks.set(task_props());
-        value_stuff.set(tot_value_since(dur));
-    }
 }
 
 fn try_persistent_signed_in(mut auth: Signal<AuthStatus>) {
@@ -74,24 +71,54 @@ fn try_persistent_signed_in(mut auth: Signal<AuthStatus>) {
 struct StateInner {
     auth_status: Signal<AuthStatus>,
     tasktype: Signal<String>,
-    tasks: Signal<Vec<TaskProp>>,
-    value_stuff: Signal<f32>,
-    is_syncing: Signal<bool>,
+    // Loaded once, mutated in place and persisted in the background.
+    tasks: Signal<Tasks>,
+    sync_status: Signal<sync::SyncStatus>,
+    // When the last sync succeeded.
+    last_synced: Signal<Option<std::time::Duration>>,
     selected_dur: Signal<String>,
+    // Seconds between recomputing the priorities on the home page.
+    tick_rate: Signal<String>,
+    // How many milliseconds the server's clock is ahead of ours, as of the last sync.
+    clock_skew: Signal<Option<i64>>,
+    // Tasks edited here and elsewhere at once, waiting for the user to pick.
+    conflicts: Signal<Vec<conflict::Conflict>>,
+    // Operations waiting to be uploaded.
+    pending_uploads: Signal<usize>,
+    // Operations the last attempt couldn't upload, and why.
+    upload_failures: Signal<Vec<(uuid::Uuid, String)>>,
 }
 
 impl StateInner {
     fn load() -> Self {
-        let auth_status = Signal::new(AuthStatus::Nope);
-        try_persistent_signed_in(auth_status.clone());
+        let auth_status = match cache::sync_server() {
+            // The token was checked when the server was set, the first sync tells if it still works.
+            Some(config) => Signal::new(AuthStatus::Auth(AuthUser {
+                uid: backend::SelfHosted::new(config).uid().to_string(),
+            })),
+            None => {
+                let auth_status = Signal::new(AuthStatus::Nope);
+                try_persistent_signed_in(auth_status.clone());
+                auth_status
+            }
+        };
+        let tasks = Tasks::load_offline();
+        let pending_uploads = outbox::count(&tasks);
 
         Self {
             auth_status,
             tasktype: Signal::new(String::from("disc")),
-            tasks: Signal::new(task_props()),
-            value_stuff: Signal::new(tot_value_since(Duration::from_secs(86400))),
-            is_syncing: Signal::new(false),
+            tasks: Signal::new(tasks),
+            sync_status: Signal::new(sync::SyncStatus::default()),
+            last_synced: Signal::new(None),
             selected_dur: Signal::new(String::from("1")),
+            tick_rate: Signal::new(
+                block_on(cache::load_tick_rate()).unwrap_or_else(|| String::from("60")),
+            ),
+            clock_skew: Signal::new(None),
+            conflicts: Signal::new(cache::conflicts()),
+            pending_uploads: Signal::new(pending_uploads),
+            upload_failures: Signal::new(vec![]),
         }
     }
 }
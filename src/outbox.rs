@@ -0,0 +1,162 @@
+use crate::cache;
+use crate::journal::{Journal, Op};
+use crate::sync;
+use crate::task::{TaskID, Tasks};
+use crate::{log, State};
+use dioxus::prelude::*;
+use gloo::events::EventListener;
+use gloo::timers::future::TimeoutFuture;
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::time::Duration;
+use uuid::Uuid;
+
+// Waits between retries double up to this.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+thread_local! {
+    // Whether a flush is under way, there's only ever one.
+    static FLUSHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// How long to wait before retrying after `failures` failed attempts in a row.
+fn backoff(failures: u32) -> Duration {
+    Duration::from_secs(1 << failures.min(16)).min(MAX_BACKOFF)
+}
+
+/// The operations of the journal that are waiting to be uploaded. Ones that aren't in the journal
+/// anymore, say because it was quarantined, are dropped.
+fn pending<'a>(journal: &'a Journal, ids: &[Uuid]) -> Vec<&'a Op> {
+    let ids: HashSet<&Uuid> = ids.iter().collect();
+    journal
+        .ops()
+        .iter()
+        .filter(|op| ids.contains(&op.id))
+        .collect()
+}
+
+/// Queues operations made on this device for uploading, apart from the ones on `local_only`
+/// tasks. The queue is stored, so whatever isn't uploaded before the page is closed is sent the
+/// next time it's opened.
+pub fn push(ops: &[Op], local_only: &HashSet<TaskID>) {
+    let state = use_context::<State>();
+    let mut count = state.inner.lock().unwrap().pending_uploads;
+
+    let mut ids = cache::outbox();
+    ids.extend(sync::shared(ops, local_only).map(|op| op.id));
+    cache::save_outbox(&ids);
+    count.set(ids.len());
+
+    flush(&state);
+}
+
+/// Takes operations off the queue, say when a sync uploaded them.
+pub fn remove(state: &State, sent: &HashSet<Uuid>) {
+    let mut count = state.inner.lock().unwrap().pending_uploads;
+
+    let mut ids = cache::outbox();
+    ids.retain(|id| !sent.contains(id));
+    cache::save_outbox(&ids);
+    count.set(ids.len());
+}
+
+/// Uploads the queue if we're signed in, retrying with backoff until it's empty. Comes back
+/// right away when the browser goes online again.
+pub fn flush(state: &State) {
+    let Some(backend) = sync::backend(state) else {
+        return;
+    };
+    if FLUSHING.replace(true) {
+        return;
+    }
+
+    let store = state.inner.lock().unwrap().tasks;
+    let mut count = state.inner.lock().unwrap().pending_uploads;
+    let mut upload_failures = state.inner.lock().unwrap().upload_failures;
+
+    wasm_bindgen_futures::spawn_local(async move {
+        let mut failures = 0;
+
+        loop {
+            let ops: Vec<Op> = pending(store.peek().journal(), &cache::outbox())
+                .into_iter()
+                .cloned()
+                .collect();
+            if ops.is_empty() {
+                break;
+            }
+
+            let local_only = store.peek().local_only();
+            let failed = sync::upload(&backend, &ops, &local_only).await;
+            let uploaded: Vec<Op> = sync::shared(&ops, &local_only)
+                .filter(|op| !failed.iter().any(|(id, _)| *id == op.id))
+                .cloned()
+                .collect();
+            sync::save_legacy(&backend, store, &uploaded).await;
+
+            // More may have been queued while uploading.
+            let mut ids = cache::outbox();
+            ids.retain(|id| {
+                failed.iter().any(|(failed, _)| failed == id) || !ops.iter().any(|op| op.id == *id)
+            });
+            cache::save_outbox(&ids);
+            count.set(ids.len());
+
+            if failed.is_empty() {
+                failures = 0;
+                upload_failures.set(vec![]);
+                continue;
+            }
+
+            upload_failures.set(failed);
+            failures += 1;
+            let wait = backoff(failures);
+            log(("retrying uploads in seconds: ", wait.as_secs()));
+            retry_after(wait).await;
+        }
+
+        cache::save_outbox(&[]);
+        count.set(0);
+        FLUSHING.set(false);
+    });
+}
+
+/// Waits for `wait`, or until the browser goes online.
+async fn retry_after(wait: Duration) {
+    let (online, went_online) = futures::channel::oneshot::channel();
+    let _listener = EventListener::once(&gloo::utils::window(), "online", move |_| {
+        let _ = online.send(());
+    });
+
+    futures::future::select(TimeoutFuture::new(wait.as_millis() as u32), went_online).await;
+}
+
+/// The number of operations waiting to be uploaded, for the state's initial value.
+pub fn count(tasks: &Tasks) -> usize {
+    pending(tasks.journal(), &cache::outbox()).len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::OpKind;
+    use crate::task::Version;
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), Duration::from_secs(2));
+        assert_eq!(backoff(4), Duration::from_secs(16));
+        assert_eq!(backoff(9), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn test_pending() {
+        let op = || Op::new(Uuid::new_v4(), Version::default(), OpKind::Deleted);
+        let journal = Journal::from(vec![op(), op(), op()]);
+        let ids = [journal.ops()[2].id, Uuid::new_v4(), journal.ops()[0].id];
+
+        let pending: Vec<Uuid> = pending(&journal, &ids).iter().map(|op| op.id).collect();
+        assert_eq!(pending, [journal.ops()[0].id, journal.ops()[2].id]);
+    }
+}
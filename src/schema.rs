@@ -0,0 +1,152 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Upgrades a document by one version.
+type Migration = fn(Value) -> Result<Value, String>;
+
+/// A kind of stored document and the migrations it has gone through. A document of version `n`
+/// is upgraded by applying the migrations from index `n` on, so adding one bumps the version.
+/// Documents from before there were versions are version 0.
+pub struct Schema {
+    pub name: &'static str,
+    migrations: &'static [Migration],
+}
+
+/// Unversioned documents have the same shape as version 1.
+fn versioned(val: Value) -> Result<Value, String> {
+    Ok(val)
+}
+
+/// The local journal, a list of operations which are versioned on their own.
+pub const JOURNAL: Schema = Schema {
+    name: "journal",
+    migrations: &[versioned],
+};
+
+/// A single operation, locally and on the server.
+pub const OP: Schema = Schema {
+    name: "op",
+    migrations: &[versioned],
+};
+
+/// Task metadata as stored on the server before the journal.
+pub const METADATA: Schema = Schema {
+    name: "metadata",
+    migrations: &[versioned],
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unreadable {
+    /// Written by a newer version of the app. It should be kept as is, so that the data isn't
+    /// lost once this device is updated.
+    Newer(u32),
+    Corrupt(String),
+}
+
+impl std::fmt::Display for Unreadable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Newer(version) => write!(f, "written by a newer version ({})", version),
+            Self::Corrupt(e) => write!(f, "corrupt: {}", e),
+        }
+    }
+}
+
+impl Schema {
+    pub fn version(&self) -> u32 {
+        self.migrations.len() as u32
+    }
+
+    /// The document in an envelope marking its version.
+    pub fn wrap<T: Serialize>(&self, data: &T) -> Value {
+        json!({
+            "v": self.version(),
+            "data": serde_json::to_value(data).unwrap(),
+        })
+    }
+
+    /// Reads a document that may or may not be in an envelope.
+    pub fn load<T: DeserializeOwned>(&self, val: Value) -> Result<T, Unreadable> {
+        match val {
+            Value::Object(mut obj) if obj.len() == 2 && obj.contains_key("v") => {
+                let version = obj.get("v").and_then(Value::as_u64);
+                match (version, obj.remove("data")) {
+                    (Some(version), Some(data)) => self.load_at(version as u32, data),
+                    _ => Err(Unreadable::Corrupt(format!("bad {} envelope", self.name))),
+                }
+            }
+            val => self.load_at(0, val),
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self, s: &str) -> Result<T, Unreadable> {
+        let val = serde_json::from_str(s).map_err(|e| Unreadable::Corrupt(e.to_string()))?;
+        self.load(val)
+    }
+
+    /// Reads a document whose version is stored separately from it.
+    pub fn load_at<T: DeserializeOwned>(&self, version: u32, data: Value) -> Result<T, Unreadable> {
+        if version > self.version() {
+            return Err(Unreadable::Newer(version));
+        }
+
+        let mut data = data;
+        for migrate in &self.migrations[version as usize..] {
+            data = migrate(data).map_err(Unreadable::Corrupt)?;
+        }
+
+        serde_json::from_value(data).map_err(|e| Unreadable::Corrupt(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn renamed(mut val: Value) -> Result<Value, String> {
+        let obj = val.as_object_mut().ok_or("not an object")?;
+        let name = obj.remove("title").ok_or("no title")?;
+        obj.insert("name".to_string(), name);
+        Ok(val)
+    }
+
+    const TEST: Schema = Schema {
+        name: "test",
+        migrations: &[versioned, renamed],
+    };
+
+    #[derive(Debug, serde::Deserialize, Serialize, PartialEq)]
+    struct Doc {
+        name: String,
+    }
+
+    #[test]
+    fn test_migrations() {
+        let doc = Doc {
+            name: "dishes".into(),
+        };
+
+        let unversioned = json!({"title": "dishes"});
+        assert_eq!(TEST.load::<Doc>(unversioned).unwrap(), doc);
+
+        let v1 = json!({"v": 1, "data": {"title": "dishes"}});
+        assert_eq!(TEST.load::<Doc>(v1).unwrap(), doc);
+        assert_eq!(TEST.load::<Doc>(TEST.wrap(&doc)).unwrap(), doc);
+    }
+
+    #[test]
+    fn test_newer_and_corrupt() {
+        let newer = json!({"v": 3, "data": {"name": "dishes", "colour": "blue"}});
+        assert_eq!(TEST.load::<Doc>(newer), Err(Unreadable::Newer(3)));
+
+        assert!(matches!(
+            TEST.load::<Doc>(json!({"v": 2, "data": 5})),
+            Err(Unreadable::Corrupt(_))
+        ));
+        assert!(matches!(
+            TEST.parse::<Doc>("{\"v\": 2, "),
+            Err(Unreadable::Corrupt(_))
+        ));
+    }
+}
@@ -1,145 +1,998 @@
+use crate::backend::{Firestore, LegacyChange, Remote, SelfHosted, Subscription, SyncBackend};
 use crate::cache;
-use crate::firebase;
-use crate::task::{LogRecord, MetaData, Task, TaskLog, Tasks};
-use crate::{log, State};
+use crate::clock;
+use crate::conflict;
+use crate::journal::{self, Journal, Op, OpKind};
+use crate::outbox;
+use crate::task::{Field, Task, TaskID, Tasks};
+use crate::{log, utils, State};
 use dioxus::prelude::*;
-use std::collections::HashMap;
+use gloo::timers::future::TimeoutFuture;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use uuid::Uuid;
 
-#[derive(Default)]
-struct SyncResult {
-    // Tasks that should be upserted
-    send_up: Vec<Task>,
-    // Tasks that should be downloaded
-    download: HashMap<Uuid, MetaData>,
-}
-
-#[derive(Default, Debug)]
-struct Syncer {
-    // 'Task' is from cache, 'MetaData' is from the server.
-    pairs: Vec<(Task, MetaData)>,
-    // Tasks that are new from server and was not present in the cache
-    new_from_server: HashMap<Uuid, MetaData>,
-    // Tasks that were not found on the server but were in the cache
-    new_offline: Vec<Task>,
-}
-
-impl Syncer {
-    fn new(mut online: HashMap<Uuid, MetaData>, offline: Tasks) -> Self {
-        let mut selv = Self::default();
-        for (_, off_task) in offline.0 {
-            match online.remove(&off_task.id) {
-                Some(ontask) => {
-                    selv.pairs.push((off_task, ontask));
-                }
-                None => {
-                    selv.new_offline.push(off_task);
-                }
-            };
-        }
+type UnixTime = Duration;
 
-        selv.new_from_server = online;
+/// How syncing is going, for showing it to the user.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum SyncStatus {
+    #[default]
+    Idle,
+    Syncing,
+    Error(String),
+    // The browser says it's offline, we'll sync when it's back.
+    Offline,
+}
 
-        selv
+// Local changes are synced once none have been made for this long.
+const DEBOUNCE_MILLIS: u32 = 5000;
+
+// Operations stored shortly before the cursor are fetched again, in case the server's timestamps
+// and the order it made writes visible in disagree.
+const CURSOR_OVERLAP: Duration = Duration::from_secs(60);
+
+/// How far this device got in the last successful sync.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SyncCursor {
+    // When the server stored the newest operation we've received.
+    pub server: Option<UnixTime>,
+    // How many operations of the journal had been sent up, and the id of the last of them.
+    pub uploaded: usize,
+    pub last_op: Option<Uuid>,
+}
+
+impl SyncCursor {
+    /// The first `uploaded` operations of the journal have been sent up.
+    fn new(server: Option<UnixTime>, journal: &Journal, uploaded: usize) -> Self {
+        Self {
+            server,
+            uploaded,
+            last_op: uploaded.checked_sub(1).map(|last| journal.ops()[last].id),
+        }
     }
 
-    fn sync(self) -> SyncResult {
-        let mut res = SyncResult::default();
-        for (off, on) in self.pairs {
-            if off.metadata.updated > on.updated {
-                res.send_up.push(off);
-            } else if off.metadata.updated < on.updated {
-                res.download.insert(off.id, on);
-            }
+    /// A cursor is only good for the journal it was taken from. One that's been replaced since,
+    /// say after it was quarantined, is synced in full again.
+    fn is_valid_for(&self, journal: &Journal) -> bool {
+        match self.uploaded.checked_sub(1) {
+            Some(last) => journal.ops().get(last).map(|op| op.id) == self.last_op,
+            None => true,
         }
+    }
+
+    /// Operations made or received since the last successful sync.
+    fn unsent<'a>(&self, journal: &'a Journal) -> &'a [Op] {
+        &journal.ops()[self.uploaded.min(journal.ops().len())..]
+    }
+
+    /// Operations `device` made since the last successful sync, uploaded or not. Other devices
+    /// haven't heard of them, so they may change the same fields regardless.
+    fn made_since(&self, journal: &Journal, device: Uuid) -> Vec<Op> {
+        self.unsent(journal)
+            .iter()
+            .filter(|op| op.version.device == device)
+            .cloned()
+            .collect()
+    }
+}
+
+/// The operations that may leave this device, i.e. aren't on local only tasks. Marking a task
+/// local only or not does leave it, so that other devices that have the task follow suit.
+pub fn shared<'a>(ops: &'a [Op], local_only: &'a HashSet<TaskID>) -> impl Iterator<Item = &'a Op> {
+    ops.iter().filter(|op| {
+        !local_only.contains(&op.task) || matches!(op.kind, OpKind::Edited(Field::LocalOnly(_)))
+    })
+}
+
+/// What the server has for a sync, before any of it is applied.
+#[derive(Debug, Clone)]
+pub struct Fetched {
+    cursor: SyncCursor,
+    remote: Vec<Op>,
+    // The operations that are on the server, as opposed to imported from before the journal.
+    remote_ids: HashSet<Uuid>,
+    newest_stored: Option<UnixTime>,
+    legacy: bool,
+}
+
+impl Fetched {
+    /// The operations the sync would send up.
+    fn send_up(&self, journal: &Journal, local_only: &HashSet<TaskID>) -> Vec<Op> {
+        shared(self.cursor.unsent(journal), local_only)
+            .filter(|op| !self.remote_ids.contains(&op.id))
+            .cloned()
+            .collect()
+    }
+
+    /// What syncing would do, task by task.
+    pub fn preview(&self, tasks: &Tasks) -> Vec<TaskPreview> {
+        let download: Vec<Op> = self
+            .remote
+            .iter()
+            .filter(|op| !tasks.journal().contains(op.id))
+            .cloned()
+            .collect();
+        let upload = self.send_up(tasks.journal(), &tasks.local_only());
+        preview(tasks.by_id(), &download, &upload)
+    }
+}
+
+/// Gets what the backend has that we might not, going by the cursor. With `legacy`, the tasks
+/// stored before there was a journal are imported too.
+pub async fn fetch<B: SyncBackend>(
+    backend: &B,
+    cursor: SyncCursor,
+    legacy: bool,
+) -> Result<Fetched, String> {
+    let since = cursor
+        .server
+        .map(|server| server.saturating_sub(CURSOR_OVERLAP));
+    let (mut remote, newest_stored) = backend.load_ops(since).await?;
+    let remote_ids: HashSet<Uuid> = remote.iter().map(|op| op.id).collect();
+
+    if legacy {
+        remote.extend(Journal::migrate(&backend.load_legacy().await?));
+    }
+
+    Ok(Fetched {
+        cursor,
+        remote,
+        remote_ids,
+        newest_stored,
+        legacy,
+    })
+}
+
+/// How sending up went.
+#[derive(Debug)]
+pub struct Sent {
+    // The operations that couldn't be stored, with why.
+    pub failed: Vec<(Uuid, String)>,
+    // The operations the backend is now known to have.
+    pub stored: HashSet<Uuid>,
+    // The ones of them this sync sent up.
+    pub sent_up: Vec<Op>,
+    pub cursor: SyncCursor,
+}
+
+/// Sends up what the backend doesn't have, once what was fetched has been added to `journal`.
+pub async fn send<B: SyncBackend>(
+    backend: &B,
+    journal: &Journal,
+    fetched: &Fetched,
+    local_only: &HashSet<TaskID>,
+) -> Sent {
+    let send_up = fetched.send_up(journal, local_only);
+    let failed = backend.append_ops(&send_up).await;
+
+    // Everything before the first failure is on the server, the rest is sent again next time.
+    let uploaded = journal
+        .ops()
+        .iter()
+        .position(|op| failed.iter().any(|(id, _)| *id == op.id))
+        .unwrap_or(journal.ops().len());
+    let server = fetched.newest_stored.max(fetched.cursor.server);
+
+    let sent_up: Vec<Op> = send_up
+        .into_iter()
+        .filter(|op| !failed.iter().any(|(id, _)| *id == op.id))
+        .collect();
+    let stored = sent_up
+        .iter()
+        .map(|op| op.id)
+        .chain(fetched.remote_ids.iter().copied())
+        .collect();
 
-        for task in self.new_from_server {
-            res.download.insert(task.0, task.1);
+    Sent {
+        failed,
+        stored,
+        sent_up,
+        cursor: SyncCursor::new(server, journal, uploaded),
+    }
+}
+
+/// The server set in settings if there is one, or else Firestore if we're signed in.
+pub fn backend(state: &State) -> Option<Remote> {
+    if let Some(config) = cache::sync_server() {
+        return Some(Remote::SelfHosted(SelfHosted::new(config)));
+    }
+    state
+        .auth_user()
+        .map(|user| Remote::Firestore(Firestore { user }))
+}
+
+/// Uploads the operations, leaving out the ones on `local_only` tasks. Returns the ones that
+/// failed, with why.
+pub async fn upload<B: SyncBackend>(
+    backend: &B,
+    ops: &[Op],
+    local_only: &HashSet<TaskID>,
+) -> Vec<(Uuid, String)> {
+    let ops: Vec<Op> = shared(ops, local_only).cloned().collect();
+    let failed = backend.append_ops(&ops).await;
+
+    for (id, e) in &failed {
+        log(("failed to upload operation: ", id, e));
+    }
+    failed
+}
+
+/// What clients from before the journal need written for the uploaded operations, task by task.
+pub fn legacy_changes(tasks: &HashMap<TaskID, Task>, uploaded: &[Op]) -> Vec<LegacyChange> {
+    let mut changes: Vec<LegacyChange> = vec![];
+    for op in uploaded {
+        // Imported from the legacy documents, so they're there already.
+        if op.version.device.is_nil() {
+            continue;
         }
+        // Older clients don't know to keep it to themselves.
+        let Some(task) = tasks.get(&op.task).filter(|task| !task.metadata.local_only) else {
+            continue;
+        };
+        // Their log documents only say when and how much, so deletions aren't passed on: that
+        // would mean deleting documents they may have written themselves.
+        let record = match &op.kind {
+            OpKind::Logged(record) => match task.log.get(record.id) {
+                Some(record) => Some(record),
+                None => continue,
+            },
+            OpKind::LogDeleted(_) => continue,
+            _ => None,
+        };
 
-        for task in self.new_offline {
-            res.send_up.push(task);
+        let change = match changes.iter_mut().position(|change| change.task == op.task) {
+            Some(i) => &mut changes[i],
+            None => {
+                changes.push(LegacyChange {
+                    task: op.task,
+                    metadata: None,
+                    records: vec![],
+                });
+                changes.last_mut().unwrap()
+            }
+        };
+        match record {
+            Some(record) => {
+                if !change.records.iter().any(|r| r.id == record.id) {
+                    change.records.push(record);
+                }
+            }
+            None => change.metadata = Some(task.metadata.clone()),
         }
+    }
+    changes
+}
 
-        res
+/// Writes what the uploaded operations changed for clients from before the journal, see
+/// `SyncBackend::save_legacy`. Failing only means those clients miss the changes for now.
+pub async fn save_legacy<B: SyncBackend>(backend: &B, store: Signal<Tasks>, uploaded: &[Op]) {
+    let changes = legacy_changes(store.peek().by_id(), uploaded);
+    if changes.is_empty() {
+        return;
+    }
+    if let Err(e) = backend.save_legacy(&changes).await {
+        log(("failed to write legacy documents: ", e));
     }
 }
 
-#[derive(Default)]
-pub struct LogSyncRes {
-    pub send_up: Vec<LogRecord>,
-    pub save: TaskLog,
-    pub id: Uuid,
-    pub user_id: String,
+thread_local! {
+    // Increases with every local change, so that only the last of a burst of them syncs.
+    static CHANGES: Cell<u64> = const { Cell::new(0) };
+    // Whether a sync was asked for while one was under way, so another runs once it's done.
+    static RESYNC: Cell<bool> = const { Cell::new(false) };
 }
 
-pub fn sync_tasks(mut is_syncing: Signal<bool>) {
+/// Syncs a few seconds after the last of a burst of local changes.
+pub fn sync_soon() {
     let state = use_context::<State>();
+    let change = CHANGES.with(|changes| {
+        changes.set(changes.get() + 1);
+        changes.get()
+    });
 
-    let x = (*state.inner.lock().unwrap().auth_status.read()).clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        TimeoutFuture::new(DEBOUNCE_MILLIS).await;
+        if CHANGES.with(Cell::get) == change {
+            sync_tasks(&state);
+        }
+    });
+}
 
-    let Some(user) = x.user() else {
-        State::refresh();
+/// Keeps a task off the server, or lets it sync again. This is an operation that syncs, so
+/// every device that has the task stops or starts uploading it. Its operations are queued for
+/// uploading again in case the server doesn't have them, or taken off the queue. Making it
+/// local only can also delete what the server has of it, though devices that already have it
+/// keep their copy.
+pub fn set_local_only(state: &State, task: TaskID, local_only: bool, delete_remote: bool) {
+    let mut store = state.inner.lock().unwrap().tasks;
+    store.write().set_local_only(task, local_only);
+
+    let ops: Vec<Op> = store
+        .peek()
+        .journal()
+        .ops()
+        .iter()
+        .filter(|op| op.task == task)
+        .cloned()
+        .collect();
+
+    if !local_only {
+        outbox::push(&ops, &store.peek().local_only());
         return;
+    }
+
+    // The marking itself still goes up.
+    let (marks, private): (Vec<Op>, Vec<Op>) = ops
+        .into_iter()
+        .partition(|op| matches!(op.kind, OpKind::Edited(Field::LocalOnly(_))));
+    let ids: Vec<Uuid> = private.iter().map(|op| op.id).collect();
+    outbox::remove(state, &ids.iter().copied().collect());
+
+    let Some(backend) = backend(state) else {
+        return;
+    };
+    if delete_remote {
+        let local_only = store.peek().local_only();
+        wasm_bindgen_futures::spawn_local(async move {
+            let ids: Vec<Uuid> = ids
+                .iter()
+                .chain(marks.iter().map(|op| &op.id))
+                .copied()
+                .collect();
+            if let Err(e) = backend.delete_task(task, &ids).await {
+                log(("failed to delete remote copies: ", e));
+                return;
+            }
+            // Devices that haven't synced since need to hear that it's local only, or they'd
+            // upload it again.
+            upload(&backend, &marks, &local_only).await;
+        });
+    }
+}
+
+/// Merges operations from the server into the tasks, keeping note of conflicts with changes
+/// made here since the sync `cursor` is from.
+fn receive(state: &State, remote: Vec<Op>, cursor: &SyncCursor) {
+    let mut store = state.inner.lock().unwrap().tasks;
+    let mut conflicts = state.inner.lock().unwrap().conflicts;
+
+    let device = cache::device_id();
+    let theirs: Vec<Op> = remote
+        .iter()
+        .filter(|op| op.version.device != device && !store.peek().journal().contains(op.id))
+        .cloned()
+        .collect();
+    let mine = cursor.made_since(store.peek().journal(), device);
+    let found = conflict::detect(store.peek().by_id(), &mine, &theirs);
+    if !found.is_empty() {
+        log(("conflicting changes: ", found.len()));
+        conflict::merge(&mut conflicts.write(), found);
+        cache::save_conflicts(&conflicts.peek());
+    }
+
+    if let Some(newest) = remote.iter().map(|op| op.version.clock).max() {
+        clock::observe(newest);
+    }
+
+    store.write().apply_remote(remote);
+    store.read().save_offline();
+}
+
+/// Merges operations other devices make as the server gets them, until the subscription is
+/// dropped. Starts from where the last sync got to, so there's nothing to listen from before
+/// this device has synced.
+pub fn subscribe(state: &State) -> Option<Subscription> {
+    let backend = backend(state)?;
+    let cursor = cache::sync_cursor(backend.uid())?;
+    let uid = backend.uid().to_string();
+    let store = state.inner.lock().unwrap().tasks;
+    let state = state.clone();
+
+    backend.subscribe(
+        cursor
+            .server
+            .unwrap_or_default()
+            .saturating_sub(CURSOR_OVERLAP),
+        Box::new(move |remote| {
+            // Our own operations come back too.
+            let new: Vec<Op> = remote
+                .into_iter()
+                .filter(|op| !store.peek().journal().contains(op.id))
+                .collect();
+            if !new.is_empty() {
+                log(("received operations: ", new.len()));
+                let cursor = cache::sync_cursor(&uid)
+                    .filter(|cursor| cursor.is_valid_for(store.peek().journal()))
+                    .unwrap_or_default();
+                receive(&state, new, &cursor);
+            }
+        }),
+    )
+}
+
+/// Syncs if we're signed in and not syncing already.
+pub fn sync_tasks(state: &State) {
+    start(state, None);
+}
+
+/// Syncs what was fetched for a preview, rather than fetching again. Returns whether the sync
+/// started, it doesn't while signed out, offline or syncing already.
+pub fn apply_fetched(state: &State, fetched: Fetched) -> bool {
+    start(state, Some(fetched))
+}
+
+fn start(state: &State, fetched: Option<Fetched>) -> bool {
+    let Some(backend) = backend(state) else {
+        return false;
     };
 
-    let task_future = firebase::load_all_tasks(&user);
-    let offline_tasks = Tasks::load_offline();
+    let mut status = state.inner.lock().unwrap().sync_status;
+    if *status.peek() == SyncStatus::Syncing {
+        // What was fetched for a preview is stale by then, it's up to the user to look again.
+        if fetched.is_none() {
+            RESYNC.set(true);
+        }
+        return false;
+    }
+    if !utils::is_online() {
+        status.set(SyncStatus::Offline);
+        return false;
+    }
+
+    let mut last_synced = state.inner.lock().unwrap().last_synced;
+    let state = state.clone();
+    status.set(SyncStatus::Syncing);
 
     wasm_bindgen_futures::spawn_local(async move {
-        is_syncing.set(true);
-        let online_tasks = MetaData::from_jsvalue(task_future.await.unwrap());
+        let res = match fetched {
+            Some(fetched) => Ok(fetched),
+            None => fetch_for(&state, &backend).await,
+        };
+        let res = match res {
+            Ok(fetched) => sync(&state, &backend, fetched).await,
+            Err(e) => Err(e),
+        };
 
-        let res = Syncer::new(online_tasks, offline_tasks).sync();
+        match res {
+            Ok(()) => {
+                let now = utils::current_time();
+                cache::save_last_synced(backend.uid(), now);
+                last_synced.set(Some(now));
+                status.set(if utils::is_online() {
+                    SyncStatus::Idle
+                } else {
+                    SyncStatus::Offline
+                });
+            }
+            Err(e) => {
+                log(("sync failed: ", &e));
+                status.set(if utils::is_online() {
+                    SyncStatus::Error(e)
+                } else {
+                    SyncStatus::Offline
+                });
+            }
+        }
 
-        let futs: Vec<_> = res
-            .send_up
-            .iter()
-            .map(|task| firebase::send_task_to_firestore(user.uid.clone(), &task))
-            .collect();
+        // Picks up the changes made while syncing.
+        if RESYNC.replace(false) {
+            start(&state, None);
+        }
+    });
+    true
+}
+
+/// Fetches from where this device's last sync got to, noting how far off our clock is.
+pub async fn fetch_for<B: SyncBackend>(state: &State, backend: &B) -> Result<Fetched, String> {
+    let store = state.inner.lock().unwrap().tasks;
+    let mut clock_skew = state.inner.lock().unwrap().clock_skew;
+    let cursor = cache::sync_cursor(backend.uid())
+        .filter(|cursor| cursor.is_valid_for(store.peek().journal()))
+        .unwrap_or_default();
+
+    let probe_sent = utils::current_time();
+    if let Ok(server) = backend.server_time().await {
+        let local = (probe_sent + utils::current_time()) / 2;
+        clock_skew.set(Some(server.as_millis() as i64 - local.as_millis() as i64));
+        clock::observe(server.into());
+    }
+
+    fetch(backend, cursor, !cache::legacy_imported(backend.uid())).await
+}
+
+/// Applies the operations we don't have, and sends up the ones the server doesn't have.
+async fn sync<B: SyncBackend>(
+    state: &State,
+    backend: &B,
+    mut fetched: Fetched,
+) -> Result<(), String> {
+    let store = state.inner.lock().unwrap().tasks;
+    let mut upload_failures = state.inner.lock().unwrap().upload_failures;
+
+    let remote = std::mem::take(&mut fetched.remote);
+    receive(state, remote, &fetched.cursor);
+
+    let journal = store.read().journal().clone();
+    let local_only = store.peek().local_only();
+    let sent = send(backend, &journal, &fetched, &local_only).await;
+    for (id, e) in &sent.failed {
+        log(("failed to upload operation: ", id, e));
+    }
+    save_legacy(backend, store, &sent.sent_up).await;
+
+    if fetched.legacy && sent.failed.is_empty() {
+        cache::set_legacy_imported(backend.uid());
+    }
+    cache::save_sync_cursor(backend.uid(), &sent.cursor);
+
+    // Queued operations that made it up now don't need retrying, the rest are retried.
+    outbox::remove(state, &sent.stored);
+    let result = match sent.failed.first() {
+        Some((_, e)) => Err(format!(
+            "{} changes failed to upload: {}",
+            sent.failed.len(),
+            e
+        )),
+        None => Ok(()),
+    };
+    upload_failures.set(sent.failed);
+    outbox::flush(state);
+
+    result
+}
+
+/// What a sync would do to one task.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskPreview {
+    pub name: String,
+    // How many operations would be sent up and brought down.
+    pub upload: usize,
+    pub download: usize,
+    // How the task would change on this device, a line for each part.
+    pub changes: Vec<String>,
+}
+
+/// What applying `download` to `tasks` would change, and what's sent up, task by task.
+fn preview(tasks: &HashMap<TaskID, Task>, download: &[Op], upload: &[Op]) -> Vec<TaskPreview> {
+    let mut after = tasks.clone();
+    journal::apply_all(&mut after, download);
+
+    let touched: HashSet<TaskID> = download.iter().chain(upload).map(|op| op.task).collect();
+    let mut previews: Vec<TaskPreview> = touched
+        .into_iter()
+        .filter_map(|id| {
+            let (before, after) = (tasks.get(&id), after.get(&id));
+            let name = after.or(before)?.metadata.name.clone();
+            Some(TaskPreview {
+                name,
+                upload: upload.iter().filter(|op| op.task == id).count(),
+                download: download.iter().filter(|op| op.task == id).count(),
+                changes: changes(before, after?),
+            })
+        })
+        .collect();
+
+    previews.sort_by(|a, b| a.name.cmp(&b.name));
+    previews
+}
+
+fn changes(before: Option<&Task>, after: &Task) -> Vec<String> {
+    let Some(before) = before else {
+        return vec![format!("new task, {} logs", after.log.records().count())];
+    };
+
+    let (old, new) = (&before.metadata, &after.metadata);
+    let mut changes = vec![];
+    if old.name != new.name {
+        changes.push(format!("renamed from {}", old.name));
+    }
+    if old.value != new.value {
+        changes.push("value changed".to_string());
+    }
+    if old.length != new.length {
+        changes.push(format!("length set to {}", utils::dur_format(new.length)));
+    }
+    if old.deleted != new.deleted {
+        changes.push(if new.deleted { "deleted" } else { "restored" }.to_string());
+    }
 
-        futures::future::join_all(futs).await;
+    let added = after
+        .log
+        .records()
+        .filter(|rec| before.log.get(rec.id).is_none())
+        .count();
+    let removed = before
+        .log
+        .records()
+        .filter(|rec| after.log.get(rec.id).is_none())
+        .count();
+    if added > 0 {
+        changes.push(format!("{} logs added", added));
+    }
+    if removed > 0 {
+        changes.push(format!("{} logs removed", removed));
+    }
 
-        for (id, task) in res.download {
-            let metadata = MetaData {
-                name: task.name,
-                value: task.value,
-                length: task.length,
-                created: task.created,
-                updated: task.updated,
-                deleted: task.deleted,
-            };
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Memory;
+    use crate::journal::OpKind;
+    use crate::task::{Field, LogRecord, MetaData, TaskLog, Version};
+    use futures::executor::block_on;
+
+    const PHONE: Uuid = Uuid::from_u128(1);
+    const LAPTOP: Uuid = Uuid::from_u128(2);
+
+    fn deleted() -> Op {
+        Op::new(Uuid::new_v4(), Version::default(), OpKind::Deleted)
+    }
+
+    fn version(secs: u64, device: Uuid) -> Version {
+        Version::new(UnixTime::from_secs(secs).into(), device)
+    }
 
-            metadata.save_offline(id).await;
+    fn created(name: &str, version: Version) -> Op {
+        let metadata = MetaData::test(name, version);
+        Op::new(Uuid::new_v4(), version, OpKind::Created(Box::new(metadata)))
+    }
+
+    fn logged(task: TaskID, secs: u64, device: Uuid) -> Op {
+        let mut record = LogRecord {
+            id: Uuid::new_v4(),
+            time: UnixTime::from_secs(secs),
+            units: 1.,
+            ..Default::default()
+        };
+        record.bump(UnixTime::from_secs(secs).into());
+        Op::new(task, version(secs, device), OpKind::Logged(record))
+    }
+
+    /// A device's side of the sync, without the app around it.
+    #[derive(Default)]
+    struct Device {
+        journal: Journal,
+        cursor: SyncCursor,
+    }
+
+    impl Device {
+        fn make(&mut self, op: &Op) {
+            self.journal.insert(op.clone());
         }
 
-        log("syncing logs");
-        let all_tasks = cache::fetch_tasks().await;
+        fn sync(&mut self, server: &Memory) -> Vec<(Uuid, String)> {
+            block_on(async {
+                let mut fetched = fetch(server, self.cursor.clone(), false).await.unwrap();
+                for op in std::mem::take(&mut fetched.remote) {
+                    self.journal.insert(op);
+                }
+                let local_only = Tasks::from_journal(self.journal.clone()).local_only();
+                let sent = send(server, &self.journal, &fetched, &local_only).await;
+                self.cursor = sent.cursor;
+                sent.failed
+            })
+        }
+
+        fn names(&self) -> Vec<String> {
+            let mut names: Vec<String> = self
+                .journal
+                .replay()
+                .values()
+                .map(|task| task.metadata.name.clone())
+                .collect();
+            names.sort();
+            names
+        }
 
-        let futs: Vec<_> = all_tasks
-            .into_keys()
+        fn logs(&self, task: TaskID) -> usize {
+            self.journal.replay()[&task].log.records().count()
+        }
+    }
+
+    #[test]
+    fn test_sync_between_devices() {
+        let server = Memory::default();
+        let (mut phone, mut laptop) = (Device::default(), Device::default());
+
+        let dishes = created("dishes", version(100, PHONE));
+        phone.make(&dishes);
+        phone.make(&logged(dishes.task, 110, PHONE));
+        assert!(phone.sync(&server).is_empty());
+
+        assert!(laptop.sync(&server).is_empty());
+        assert_eq!(laptop.names(), ["dishes"]);
+        assert_eq!(laptop.logs(dishes.task), 1);
+
+        // Both change it before hearing from the other.
+        let rename = OpKind::Edited(Field::Name("plates".into()));
+        laptop.make(&Op::new(dishes.task, version(200, LAPTOP), rename));
+        phone.make(&logged(dishes.task, 150, PHONE));
+        laptop.sync(&server);
+        phone.sync(&server);
+        laptop.sync(&server);
+
+        assert_eq!(phone.names(), ["plates"]);
+        assert_eq!(laptop.names(), ["plates"]);
+        assert_eq!(phone.logs(dishes.task), 2);
+        assert_eq!(laptop.logs(dishes.task), 2);
+        assert_eq!(phone.journal.ops().len(), laptop.journal.ops().len());
+
+        // Nothing new since, so nothing is sent again. Our own writes may come back down, but
+        // they're already known.
+        let (ops, stored) = (phone.journal.ops().len(), server.ops.borrow().len());
+        let fetched = block_on(fetch(&server, phone.cursor.clone(), false)).unwrap();
+        assert!(fetched.send_up(&phone.journal, &HashSet::new()).is_empty());
+        phone.sync(&server);
+        assert_eq!(phone.journal.ops().len(), ops);
+        assert_eq!(server.ops.borrow().len(), stored);
+    }
+
+    #[test]
+    fn test_sync_failed_upload() {
+        let server = Memory::default();
+        let mut phone = Device::default();
+
+        let dishes = created("dishes", version(100, PHONE));
+        let log = logged(dishes.task, 110, PHONE);
+        phone.make(&dishes);
+        phone.make(&log);
+        server.failing.borrow_mut().insert(log.id);
+
+        let failed = phone.sync(&server);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, log.id);
+        assert!(server.stored(dishes.id));
+        assert!(!server.stored(log.id));
+
+        // It's sent again once the server takes it.
+        server.failing.borrow_mut().clear();
+        assert!(phone.sync(&server).is_empty());
+        assert!(server.stored(log.id));
+    }
+
+    #[test]
+    fn test_sync_local_only() {
+        let server = Memory::default();
+        let (mut phone, mut laptop) = (Device::default(), Device::default());
+
+        let dishes = created("dishes", version(100, PHONE));
+        let mut diary = MetaData::test("diary", version(100, PHONE));
+        diary.local_only = true;
+        let diary = Op::new(
+            Uuid::new_v4(),
+            version(100, PHONE),
+            OpKind::Created(Box::new(diary)),
+        );
+        phone.make(&dishes);
+        phone.make(&diary);
+        phone.make(&logged(diary.task, 110, PHONE));
+        phone.sync(&server);
+
+        assert!(server
+            .ops
+            .borrow()
+            .iter()
+            .all(|(op, _)| op.task != diary.task));
+        laptop.sync(&server);
+        assert_eq!(laptop.names(), ["dishes"]);
+
+        // Marking a task local only syncs, so the other devices stop uploading it too, even
+        // when they sync everything again.
+        let mark = Op::new(
+            dishes.task,
+            version(200, LAPTOP),
+            OpKind::Edited(Field::LocalOnly(true)),
+        );
+        laptop.make(&mark);
+        laptop.sync(&server);
+        block_on(server.delete_task(dishes.task, &[])).unwrap();
+        block_on(server.append_ops(std::slice::from_ref(&mark)));
+
+        phone.sync(&server);
+        phone.make(&logged(dishes.task, 210, PHONE));
+        phone.cursor = SyncCursor::default();
+        phone.sync(&server);
+        assert_eq!(server.ops.borrow().len(), 1);
+        assert!(server.stored(mark.id));
+    }
+
+    #[test]
+    fn test_sync_legacy() {
+        let id = Uuid::new_v4();
+        let metadata = MetaData::test("dishes", version(100, Uuid::nil()));
+        let task = Task {
+            id,
+            log: TaskLog::default(),
+            metadata,
+        };
+        let server = Memory::default();
+        server.legacy.borrow_mut().insert(id, task);
+
+        let fetched = block_on(fetch(&server, SyncCursor::default(), true)).unwrap();
+        assert!(fetched.remote_ids.is_empty());
+        assert_eq!(fetched.remote.len(), 1);
+        assert_eq!(fetched.remote[0].task, id);
+    }
+
+    #[test]
+    fn test_save_legacy() {
+        let server = Memory::default();
+        let mut phone = Device::default();
+        let dishes = created("dishes", version(100, PHONE));
+        let log = logged(dishes.task, 110, PHONE);
+        phone.make(&dishes);
+        phone.make(&log);
+        let tasks = phone.journal.replay();
+
+        let changes = legacy_changes(&tasks, &[dishes.clone(), log.clone()]);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].metadata.as_ref().unwrap().name, "dishes");
+        assert_eq!(changes[0].records.len(), 1);
+
+        // A log alone leaves the task's document be, and imported operations are left out.
+        let log_only = legacy_changes(&tasks, std::slice::from_ref(&log));
+        assert_eq!(log_only[0].metadata, None);
+        assert!(legacy_changes(&tasks, &Journal::migrate(&tasks)).is_empty());
+
+        // What a client from before the journal would see.
+        block_on(server.save_legacy(&changes)).unwrap();
+        let legacy = server.legacy.borrow();
+        assert_eq!(legacy[&dishes.task].metadata.name, "dishes");
+        assert_eq!(legacy[&dishes.task].log.records().count(), 1);
+
+        // They read the id as the time in seconds and the units as a string, and panic otherwise.
+        let (id, fields) = changes[0].records[0].legacy_doc();
+        let secs: u64 = id.parse().unwrap();
+        let units: f32 = match fields.get("units").unwrap().as_str() {
+            Some(s) => s.parse().unwrap(),
+            None => 1.,
+        };
+        assert_eq!((secs, units), (110, 1.));
+    }
+
+    #[test]
+    fn test_save_legacy_skips_deleted() {
+        let mut phone = Device::default();
+        let dishes = created("dishes", version(100, PHONE));
+        let log = logged(dishes.task, 110, PHONE);
+        let OpKind::Logged(record) = &log.kind else {
+            unreachable!()
+        };
+        let delete = Op::new(
+            dishes.task,
+            version(120, PHONE),
+            OpKind::LogDeleted(record.id),
+        );
+        phone.make(&dishes);
+        phone.make(&log);
+        phone.make(&delete);
+        let tasks = phone.journal.replay();
+
+        // Older clients can't tell a deleted record from one they wrote, so it's left alone.
+        assert!(legacy_changes(&tasks, &[log, delete]).is_empty());
+    }
+
+    #[test]
+    fn test_conflict_after_upload() {
+        let server = Memory::default();
+        let (mut phone, mut laptop) = (Device::default(), Device::default());
+        let dishes = created("dishes", version(100, PHONE));
+        phone.make(&dishes);
+        phone.sync(&server);
+        laptop.sync(&server);
+
+        // The rename went up on its own, say from the outbox, so the cursor is from before it.
+        let rename = |name: &str, secs, device| {
+            let kind = OpKind::Edited(Field::Name(name.into()));
+            Op::new(dishes.task, version(secs, device), kind)
+        };
+        let plates = rename("plates", 200, PHONE);
+        phone.make(&plates);
+        assert!(block_on(server.append_ops(std::slice::from_ref(&plates))).is_empty());
+        laptop.make(&rename("cups", 210, LAPTOP));
+        laptop.sync(&server);
+
+        let fetched = block_on(fetch(&server, phone.cursor.clone(), false)).unwrap();
+        let theirs: Vec<Op> = fetched
+            .remote
             .into_iter()
-            .map(|key| TaskLog::sync_id(key, user.uid.clone()))
+            .filter(|op| op.version.device != PHONE)
             .collect();
+        let mine = phone.cursor.made_since(&phone.journal, PHONE);
+        assert_eq!(mine, [plates]);
 
-        let vals = futures::future::join_all(futs).await;
+        let found = conflict::detect(&phone.journal.replay(), &mine, &theirs);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].remote.name, "cups");
+    }
 
-        let mut outer_futs = vec![];
+    #[test]
+    fn test_cursor() {
+        let mut journal = Journal::from(vec![deleted(), deleted()]);
+        let cursor = SyncCursor::new(Some(UnixTime::from_secs(100)), &journal, 2);
+        assert!(cursor.unsent(&journal).is_empty());
 
-        for res in vals {
-            res.save.save_offline(res.id).await;
-            let futs: Vec<_> = res
-                .send_up
-                .iter()
-                .map(|x| firebase::add_task_log_to_firestore(user.uid.clone(), res.id, *x))
-                .collect();
+        let op = deleted();
+        journal.insert(op.clone());
+        assert!(cursor.is_valid_for(&journal));
+        assert_eq!(cursor.unsent(&journal), [op]);
 
-            outer_futs.push(futures::future::join_all(futs));
-        }
+        // The journal was replaced, so the cursor doesn't apply to it.
+        let replaced = Journal::from(vec![deleted(), deleted(), deleted()]);
+        assert!(!cursor.is_valid_for(&replaced));
+        assert!(!cursor.is_valid_for(&Journal::default()));
+        assert!(SyncCursor::default().is_valid_for(&replaced));
+
+        // Only the first operation made it.
+        let partial = SyncCursor::new(None, &journal, 1);
+        assert!(partial.is_valid_for(&journal));
+        assert_eq!(partial.unsent(&journal), &journal.ops()[1..]);
+    }
 
-        futures::future::join_all(outer_futs).await;
+    #[test]
+    fn test_local_only() {
+        let (private, public) = (deleted(), deleted());
+        let ops = [private.clone(), public.clone(), private.clone()];
+        let local_only = HashSet::from([private.task]);
 
-        is_syncing.set(false);
-        State::refresh();
-    });
+        let sent: Vec<&Op> = shared(&ops, &local_only).collect();
+        assert_eq!(sent, [&public]);
+        assert_eq!(shared(&ops, &HashSet::new()).count(), 3);
+
+        // Marking it is what tells other devices.
+        let kind = OpKind::Edited(Field::LocalOnly(true));
+        let mark = Op::new(private.task, Version::default(), kind);
+        assert_eq!(shared(std::slice::from_ref(&mark), &local_only).count(), 1);
+    }
+
+    #[test]
+    fn test_preview() {
+        let task = Uuid::new_v4();
+        let version = |secs| Version::new(UnixTime::from_secs(secs).into(), Uuid::nil());
+        let metadata = MetaData::test("dishes", version(100));
+        let created = Op::new(task, version(100), OpKind::Created(Box::new(metadata)));
+        let mut tasks = HashMap::default();
+        journal::apply_all(&mut tasks, std::slice::from_ref(&created));
+
+        let mut record = LogRecord {
+            id: Uuid::new_v4(),
+            time: UnixTime::from_secs(150),
+            units: 1.,
+            ..Default::default()
+        };
+        record.bump(UnixTime::from_secs(150).into());
+        let download = [
+            Op::new(
+                task,
+                version(200),
+                OpKind::Edited(Field::Name("plates".into())),
+            ),
+            Op::new(task, version(150), OpKind::Logged(record)),
+        ];
+        let upload = [Op::new(task, version(120), OpKind::Deleted)];
+
+        assert_eq!(
+            preview(&tasks, &download, &upload),
+            [TaskPreview {
+                name: "plates".to_string(),
+                upload: 1,
+                download: 2,
+                changes: vec![
+                    "renamed from dishes".to_string(),
+                    "1 logs added".to_string()
+                ],
+            }]
+        );
+
+        // A task we haven't seen is new, and nothing changes without anything to download.
+        let new = preview(&HashMap::default(), std::slice::from_ref(&created), &[]);
+        assert_eq!(new[0].changes, ["new task, 0 logs"]);
+        assert!(preview(&tasks, &[], &[]).is_empty());
+    }
 }
@@ -1,115 +1,504 @@
+use crate::backup;
 use crate::cache;
-use dioxus::prelude::*;
+use crate::clock::{self, Hlc};
+use crate::earnings::{Accumulator, Earnings};
 use futures::executor::block_on;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
 use uuid::Uuid;
 use wasm_bindgen::prelude::*;
-use web_sys::{window, Storage};
 
 type UnixTime = Duration;
 
-use crate::firebase::add_task_log_to_firestore;
-use crate::sync::LogSyncRes;
-use crate::{log, log_to_console, utils, State};
+use crate::journal::{self, Journal, Op, OpKind};
+use crate::outbox;
+use crate::schema::{self, Unreadable};
+use crate::sync;
+use crate::undo::{Action, History, Part};
+use crate::{log, log_to_console, utils};
 
-const DEFAULT_SLOPE: f32 = std::f32::consts::E + 1.;
+pub const DEFAULT_SLOPE: f32 = std::f32::consts::E + 1.;
 pub type TaskID = Uuid;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 pub struct LogRecord {
+    // Logs from before records had ids get one assigned on load, see `LogRecord::legacy_id`.
+    #[serde(default)]
+    pub id: Uuid,
     pub time: UnixTime,
     pub units: f32,
+    // When the record was last edited or deleted, used to pick a side when merging.
+    // Together with `counter` this is a hybrid logical clock timestamp.
+    #[serde(default)]
+    pub updated: UnixTime,
+    #[serde(default)]
+    pub counter: u32,
+    // Tombstone, kept around so that other devices don't re-add the record.
+    #[serde(default)]
+    pub deleted: bool,
+    // What the record was worth when it was logged, so that later edits of the task don't
+    // rewrite history. Missing for logs from before this was tracked.
+    #[serde(default)]
+    pub earned: Option<f32>,
+    // The value equation that `earned` was calculated with.
+    #[serde(default)]
+    pub params: Option<ValueEq>,
 }
 
 impl LogRecord {
     fn new(time: UnixTime, units: f32) -> Self {
-        Self { time, units }
+        Self {
+            id: Uuid::new_v4(),
+            time,
+            units,
+            updated: time,
+            counter: 0,
+            deleted: false,
+            earned: None,
+            params: None,
+        }
     }
 
-    fn new_current(units: f32) -> Self {
-        let time = utils::current_time();
-        Self::new(time, units)
+    pub fn clock(&self) -> Hlc {
+        Hlc::new(self.updated, self.counter)
+    }
+
+    fn set_clock(&mut self, clock: Hlc) {
+        self.updated = clock.time;
+        self.counter = clock.counter;
+    }
+
+    /// Whether this version of a record should replace `other` when merging.
+    fn is_newer(&self, other: &Self) -> bool {
+        if self.clock() != other.clock() {
+            return self.clock() > other.clock();
+        }
+
+        self.deleted && !other.deleted
+    }
+
+    /// Makes sure an edit wins over the previous version, whatever `now` says.
+    pub fn bump(&mut self, now: Hlc) {
+        self.set_clock(now.max(self.clock().successor()));
+    }
+
+    /// The document clients from before the journal read for this record: keyed by its time in
+    /// seconds, with the units as a string. `journal` marks it as a copy of what the operations
+    /// already have, so importing the legacy documents doesn't log it twice.
+    pub fn legacy_doc(&self) -> (String, serde_json::Value) {
+        let fields = serde_json::json!({
+            "units": self.units.to_string(),
+            "journal": true,
+        });
+        (self.time.as_secs().to_string(), fields)
+    }
+
+    /// Logs used to be identified by their time in seconds and their units, so that's what we
+    /// derive the id from. This way every device and the server end up with the same id.
+    fn legacy_id(time: UnixTime, units: f32) -> Uuid {
+        Uuid::from_u128(((time.as_secs() as u128) << 32) | units.to_bits() as u128)
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct Tasks(pub HashMap<Uuid, Task>);
+/// Every task, as derived from the journal. All changes go through here so that they're
+/// recorded as operations.
+#[derive(Debug, Clone, Default)]
+pub struct Tasks {
+    tasks: HashMap<TaskID, Task>,
+    journal: Journal,
+    history: History,
+}
 
 impl Tasks {
     pub fn load_offline() -> Self {
-        Self(block_on(cache::fetch_tasks()))
+        if let Some(journal) = block_on(cache::fetch_journal()) {
+            backup::run(&journal);
+            let mut selv = Self::from_journal(journal);
+            selv.migrate_local_only();
+            return selv;
+        }
+
+        // Data from before the journal is turned into operations once.
+        log("migrating tasks to the journal");
+        let ops = Journal::migrate(&block_on(cache::fetch_tasks()));
+        let selv = Self::from_journal(Journal::from(ops));
+        selv.save_offline();
+        selv
     }
 
-    pub fn to_vec_sorted(self) -> Vec<Task> {
-        let mut vec = vec![];
+    pub fn from_journal(journal: Journal) -> Self {
+        Self {
+            tasks: journal.replay(),
+            journal,
+            history: History::default(),
+        }
+    }
 
-        for (_, task) in self.0.into_iter() {
-            vec.push(task);
+    /// Tasks used to be kept off the server by a list on this device alone, so other devices
+    /// went on uploading them. They're marked in the journal instead, once.
+    fn migrate_local_only(&mut self) {
+        let ops: Vec<Op> = cache::local_only()
+            .into_iter()
+            .filter(|id| {
+                self.tasks
+                    .get(id)
+                    .is_some_and(|task| !task.metadata.local_only)
+            })
+            .map(|id| Self::op(id, OpKind::Edited(Field::LocalOnly(true))))
+            .collect();
+        if !ops.is_empty() {
+            for op in &ops {
+                self.journal.insert(op.clone());
+            }
+            journal::apply_all(&mut self.tasks, &ops);
+            cache::save_journal(&self.journal);
         }
+        cache::clear_local_only();
+    }
 
-        vec.sort_by_key(|t| (t.priority() * 1000.) as u32);
-        vec.reverse();
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
 
-        vec
+    pub fn all(&self) -> impl Iterator<Item = &Task> {
+        self.tasks.values()
+    }
+
+    pub fn by_id(&self) -> &HashMap<TaskID, Task> {
+        &self.tasks
     }
 
-    pub fn prune_deleted(&mut self) {
-        self.0.retain(|_, task| !task.metadata.deleted);
+    /// The tasks kept off the server.
+    pub fn local_only(&self) -> HashSet<TaskID> {
+        self.all()
+            .filter(|task| task.metadata.local_only)
+            .map(|task| task.id)
+            .collect()
     }
 
+    /// The tasks that haven't been deleted.
+    pub fn active(&self) -> impl Iterator<Item = &Task> {
+        self.all().filter(|task| !task.metadata.deleted)
+    }
+
+    /// Persists the journal in the background.
     pub fn save_offline(&self) {
-        log("starting save tasks");
+        let journal = self.journal.clone();
+
+        wasm_bindgen_futures::spawn_local(async move {
+            cache::save_journal(&journal);
+            log_to_console("Stored journal in local storage");
+            backup::run(&journal);
+        });
+    }
 
-        let mut metamap: HashMap<TaskID, MetaData> = HashMap::default();
+    pub fn get_task(&self, id: Uuid) -> Option<Task> {
+        self.tasks.get(&id).cloned()
+    }
+
+    /// Applies operations from other devices, skipping the ones we already have.
+    pub fn apply_remote(&mut self, ops: Vec<Op>) {
+        let new: Vec<Op> = ops
+            .into_iter()
+            .filter(|op| self.journal.insert(op.clone()))
+            .collect();
+        journal::apply_all(&mut self.tasks, &new);
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Records a user action, which can then be undone.
+    fn record(&mut self, ops: Vec<Op>) {
+        self.record_labelled(ops, None);
+    }
 
-        for (key, task) in &self.0 {
-            metamap.insert(*key, task.metadata.clone());
+    /// Records a user action, described by `label` rather than its first operation.
+    fn record_labelled(&mut self, ops: Vec<Op>, label: Option<&str>) {
+        if ops.is_empty() {
+            return;
         }
 
-        Self::save_metadatas(metamap);
+        let action = Action::start(&self.tasks, &ops);
+        self.commit(ops.clone());
+        let mut action = action.finish(&self.tasks, &ops);
+        if let Some(label) = label {
+            action.label = label.to_string();
+        }
+        self.history.push(action);
     }
 
-    pub fn save_metadatas(metamap: HashMap<TaskID, MetaData>) {
-        let s = serde_json::to_string(&metamap).unwrap();
-        log(("save metadatas: ", &s));
+    pub fn undo(&mut self) {
+        let parts = self.history.undo(&self.tasks);
+        self.revert(parts);
+    }
 
-        let storage: Storage = window()
-            .expect("no global `window` exists")
-            .local_storage()
-            .expect("no local storage")
-            .expect("local storage unavailable");
+    pub fn redo(&mut self) {
+        let parts = self.history.redo(&self.tasks);
+        self.revert(parts);
+    }
 
-        storage
-            .set_item("tasks", &s)
-            .expect("Unable to set item in local storage");
-        log_to_console("Stored tasks in local storage");
+    fn revert(&mut self, parts: Vec<(TaskID, Part)>) {
+        let version = Self::version();
+        let ops = parts
+            .into_iter()
+            .map(|(id, part)| match part.into_kind(version.clock) {
+                OpKind::Logged(record) => Self::logged(id, record),
+                kind => Op::new(id, version, kind),
+            })
+            .collect();
+        self.commit(ops);
     }
 
-    pub fn get_task(&self, id: Uuid) -> Option<Task> {
-        self.0.get(&id).cloned()
+    /// Adds operations recovered from damaged or backed up data, skipping the ones we have. They
+    /// aren't undoable, and are uploaded with the next sync.
+    pub fn recover(&mut self, ops: Vec<Op>) {
+        self.apply_remote(ops);
+        self.save_offline();
     }
 
-    pub fn insert(&mut self, task: Task) {
-        self.0.insert(task.id, task);
+    /// Makes the tasks look like they did in `backup`, as one action that can be undone. Changes
+    /// made since are undone by newer changes rather than removed, so this syncs normally.
+    pub fn restore(&mut self, backup: &Journal) {
+        // Brings back tasks and logs we no longer have at all.
+        self.recover(backup.ops().to_vec());
+
+        let target = backup.replay();
+        let version = Self::version();
+        let mut ops = vec![];
+
+        for task in self.tasks.values() {
+            let id = task.id;
+            let Some(old) = target.get(&id) else {
+                if !task.metadata.deleted {
+                    ops.push(Op::new(id, version, OpKind::Deleted));
+                }
+                continue;
+            };
+
+            for field in old.metadata.changes(&task.metadata) {
+                let kind = Part::Field(field).into_kind(version.clock);
+                ops.push(Op::new(id, version, kind));
+            }
+
+            for record in task.log.records() {
+                if old.log.get(record.id).is_none() {
+                    ops.push(Op::new(id, version, OpKind::LogDeleted(record.id)));
+                }
+            }
+
+            for record in old.log.records() {
+                let same = task
+                    .log
+                    .get(record.id)
+                    .is_some_and(|rec| rec.time == record.time && rec.units == record.units);
+                if !same {
+                    let part = Part::Record(record.id, Some(record.clone()));
+                    if let OpKind::Logged(record) = part.into_kind(version.clock) {
+                        ops.push(Self::logged(id, record));
+                    }
+                }
+            }
+        }
+
+        self.record_labelled(ops, Some("restored a backup"));
     }
 
-    pub fn delete_task(&mut self, id: Uuid) {
-        let mut task = self.get_task(id).unwrap();
-        task.metadata.deleted = true;
-        task.metadata.updated = utils::current_time();
-        self.insert(task);
+    /// Applies and persists operations made on this device, and uploads them if we can.
+    fn commit(&mut self, ops: Vec<Op>) {
+        if ops.is_empty() {
+            return;
+        }
+
+        for op in &ops {
+            self.journal.insert(op.clone());
+        }
+        journal::apply_all(&mut self.tasks, &ops);
         self.save_offline();
+        outbox::push(&ops, &self.local_only());
+        sync::sync_soon();
+    }
+
+    fn version() -> Version {
+        Version::new(clock::tick(), cache::device_id())
+    }
+
+    fn op(id: TaskID, kind: OpKind) -> Op {
+        Op::new(id, Self::version(), kind)
+    }
+
+    fn logged(id: TaskID, record: LogRecord) -> Op {
+        let version = Version::new(record.clock(), cache::device_id());
+        Op::new(id, version, OpKind::Logged(record))
+    }
+
+    pub fn create(&mut self, task: Task) {
+        let version = task.metadata.versions.newest();
+        self.record(vec![Op::new(
+            task.id,
+            version,
+            OpKind::Created(Box::new(task.metadata)),
+        )]);
     }
 
-    pub fn do_task(&mut self, id: Uuid, units: f32) {
-        let mut task = self.get_task(id).unwrap();
-        task.do_task(units);
+    /// Records every field of `metadata` that differs from the task's current metadata, as one
+    /// version of the task.
+    pub fn edit_metadata(&mut self, id: Uuid, metadata: MetaData) {
+        let current = &self.tasks.get(&id).unwrap().metadata;
+        let version = Self::version();
+        let ops = metadata
+            .changes(current)
+            .into_iter()
+            .map(|field| Op::new(id, version, OpKind::Edited(field)))
+            .collect();
+        self.record(ops);
+    }
+
+    /// Sets the name, value and length back to how they were as of `version`. This is an edit like
+    /// any other, so it syncs and can be undone.
+    pub fn revert_to(&mut self, id: Uuid, version: Version) {
+        let Some((_, old)) = self
+            .journal
+            .versions(id)
+            .into_iter()
+            .find(|(v, _)| *v == version)
+        else {
+            return;
+        };
+
+        let mut metadata = self.tasks.get(&id).unwrap().metadata.clone();
+        metadata.name = old.name;
+        metadata.value = old.value;
+        metadata.length = old.length;
+        self.edit_metadata(id, metadata);
+    }
+
+    /// Settles a conflict with the value picked for each field, as a change that wins over both
+    /// sides everywhere. Fields already at the picked value are left alone.
+    pub fn resolve(&mut self, id: Uuid, picked: Vec<Field>) {
+        let Some(task) = self.tasks.get(&id) else {
+            return;
+        };
+
+        let version = Self::version();
+        let ops = picked
+            .into_iter()
+            .filter(|field| task.metadata.field(field) != *field)
+            .map(|field| Op::new(id, version, Part::Field(field).into_kind(version.clock)))
+            .collect();
+        self.record_labelled(ops, Some("resolved a conflict"));
+    }
+
+    pub fn set_local_only(&mut self, id: Uuid, local_only: bool) {
+        self.record(vec![Self::op(
+            id,
+            OpKind::Edited(Field::LocalOnly(local_only)),
+        )]);
+    }
+
+    pub fn delete_task(&mut self, id: Uuid) {
+        self.record(vec![Self::op(id, OpKind::Deleted)]);
+    }
+
+    pub fn restore_task(&mut self, id: Uuid) {
+        self.record(vec![Self::op(id, OpKind::Restored)]);
+    }
+
+    pub fn do_task(&mut self, id: Uuid, units: f32, time: UnixTime) {
+        let record = self.tasks.get(&id).unwrap().completion(units, time);
+        self.record(vec![Self::logged(id, record)]);
+    }
+
+    pub fn edit_log(&mut self, id: Uuid, log_id: Uuid, time: UnixTime, units: f32) {
+        let task = self.tasks.get(&id).unwrap();
+        if let Some(record) = task.edited_record(log_id, time, units) {
+            self.record(vec![Self::logged(id, record)]);
+        }
+    }
+
+    pub fn delete_log(&mut self, id: Uuid, log_id: Uuid) {
+        self.record(vec![Self::op(id, OpKind::LogDeleted(log_id))]);
+    }
+
+    pub fn recalculate_earnings(&mut self, id: Uuid) {
+        let records = self.tasks.get(&id).unwrap().recalculated();
+        let ops = records
+            .into_iter()
+            .map(|record| Self::logged(id, record))
+            .collect();
+        self.record(ops);
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// When a field was last changed, and on which device. Changes made at the same time are
+/// ordered by device id, so that every device picks the same winner.
+#[derive(
+    Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct Version {
+    #[serde(flatten)]
+    pub clock: Hlc,
+    pub device: Uuid,
+}
+
+impl Version {
+    pub fn new(clock: Hlc, device: Uuid) -> Self {
+        Self { clock, device }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq)]
+pub struct FieldVersions {
+    pub name: Version,
+    pub value: Version,
+    pub length: Version,
+    pub deleted: Version,
+    #[serde(default)]
+    pub local_only: Version,
+}
+
+impl FieldVersions {
+    pub fn all(version: Version) -> Self {
+        Self {
+            name: version,
+            value: version,
+            length: version,
+            deleted: version,
+            local_only: version,
+        }
+    }
+
+    pub fn newest(&self) -> Version {
+        self.name
+            .max(self.value)
+            .max(self.length)
+            .max(self.deleted)
+            .max(self.local_only)
+    }
+}
+
+/// A single field of `MetaData`, with its new value.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum Field {
+    Name(String),
+    Value(ValueEq),
+    Length(Duration),
+    Deleted(bool),
+    LocalOnly(bool),
+}
+
+/// Takes `theirs` if it's newer than `mine`.
+fn merge_field<T>(mine: &mut T, my_version: &mut Version, theirs: T, their_version: Version) {
+    if their_version > *my_version {
+        *mine = theirs;
+        *my_version = their_version;
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct MetaData {
     pub name: String,
     pub value: ValueEq,
@@ -117,6 +506,11 @@ pub struct MetaData {
     pub created: UnixTime,
     pub updated: UnixTime,
     pub deleted: bool,
+    // Kept off the server by every device that has it, see `sync::shared`.
+    #[serde(default)]
+    pub local_only: bool,
+    #[serde(default)]
+    pub versions: FieldVersions,
 }
 
 impl MetaData {
@@ -128,34 +522,151 @@ impl MetaData {
             updated: time,
             value: equation,
             deleted: false,
+            local_only: false,
             length,
+            versions: FieldVersions::all(Version::new(clock::tick(), cache::device_id())),
+        }
+    }
+
+    /// A task logged every day or so, created and last changed at `version`.
+    #[cfg(test)]
+    pub fn test(name: &str, version: Version) -> Self {
+        Self {
+            name: name.to_string(),
+            value: ValueEq::Log(LogPriority::new(10., UnixTime::from_secs(86400))),
+            length: Duration::from_secs(600),
+            created: version.clock.time,
+            updated: version.clock.time,
+            deleted: false,
+            local_only: false,
+            versions: FieldVersions::all(version),
+        }
+    }
+
+    /// Metadata from before fields were versioned counts as changed when it was last updated.
+    pub fn assign_legacy_versions(&mut self) {
+        if self.versions == FieldVersions::default() {
+            self.versions = FieldVersions::all(Version::new(self.updated.into(), Uuid::nil()));
+        }
+    }
+
+    /// The fields that differ from `before`.
+    pub fn changes(&self, before: &Self) -> Vec<Field> {
+        let mut changes = vec![];
+        if self.name != before.name {
+            changes.push(Field::Name(self.name.clone()));
         }
+        if self.value != before.value {
+            changes.push(Field::Value(self.value.clone()));
+        }
+        if self.length != before.length {
+            changes.push(Field::Length(self.length));
+        }
+        if self.deleted != before.deleted {
+            changes.push(Field::Deleted(self.deleted));
+        }
+        if self.local_only != before.local_only {
+            changes.push(Field::LocalOnly(self.local_only));
+        }
+        changes
     }
 
-    pub async fn save_offline(&self, id: TaskID) {
-        log("starting save tasks");
+    /// The current value of the field that `like` is.
+    pub fn field(&self, like: &Field) -> Field {
+        match like {
+            Field::Name(_) => Field::Name(self.name.clone()),
+            Field::Value(_) => Field::Value(self.value.clone()),
+            Field::Length(_) => Field::Length(self.length),
+            Field::Deleted(_) => Field::Deleted(self.deleted),
+            Field::LocalOnly(_) => Field::LocalOnly(self.local_only),
+        }
+    }
 
-        let mut metamap = cache::fetch_metadata().await;
-        metamap.insert(id, self.clone());
-        Tasks::save_metadatas(metamap);
+    /// Sets the field, unless it's been changed by something newer than `version`.
+    pub fn apply(&mut self, field: Field, version: Version) {
+        let versions = &mut self.versions;
+        match field {
+            Field::Name(name) => merge_field(&mut self.name, &mut versions.name, name, version),
+            Field::Value(value) => {
+                merge_field(&mut self.value, &mut versions.value, value, version)
+            }
+            Field::Length(length) => {
+                merge_field(&mut self.length, &mut versions.length, length, version)
+            }
+            Field::Deleted(deleted) => {
+                merge_field(&mut self.deleted, &mut versions.deleted, deleted, version)
+            }
+            Field::LocalOnly(local_only) => merge_field(
+                &mut self.local_only,
+                &mut versions.local_only,
+                local_only,
+                version,
+            ),
+        }
+        self.updated = self.updated.max(version.clock.time);
     }
 
-    pub fn from_jsvalue(val: wasm_bindgen::JsValue) -> HashMap<Uuid, Self> {
+    /// Keeps the newest version of each field. A deletion is only undone by a restore that's
+    /// newer than it, edits to other fields leave it alone.
+    pub fn merge(&mut self, other: Self) {
+        let mine = &mut self.versions;
+        let theirs = other.versions;
+        merge_field(&mut self.name, &mut mine.name, other.name, theirs.name);
+        merge_field(&mut self.value, &mut mine.value, other.value, theirs.value);
+        merge_field(
+            &mut self.length,
+            &mut mine.length,
+            other.length,
+            theirs.length,
+        );
+        merge_field(
+            &mut self.deleted,
+            &mut mine.deleted,
+            other.deleted,
+            theirs.deleted,
+        );
+        merge_field(
+            &mut self.local_only,
+            &mut mine.local_only,
+            other.local_only,
+            theirs.local_only,
+        );
+        self.created = self.created.min(other.created);
+        self.updated = self.updated.max(other.updated);
+    }
+
+    /// Also returns when the server last accepted a write to any of the tasks.
+    pub fn from_jsvalue(val: wasm_bindgen::JsValue) -> (HashMap<Uuid, Self>, Option<UnixTime>) {
         let x: serde_json::Value = serde_wasm_bindgen::from_value(val).unwrap();
         log(("firetask: ", &x));
         let x = x.as_array().unwrap();
 
         let mut online_tasks = HashMap::default();
+        let mut server_time = None;
 
         for y in x {
-            let task = y.get("task").unwrap().as_str().unwrap();
+            if let Some(updated_at) = y.get("updated_at").and_then(|t| t.as_f64()) {
+                server_time = server_time.max(Some(UnixTime::from_millis(updated_at as u64)));
+            }
+
             let id = y.get("id").unwrap().as_str().unwrap();
-            let task: MetaData = serde_json::from_str(&task).unwrap();
-            let id: Uuid = serde_json::from_str(&id).unwrap();
+            let version = y.get("v").and_then(|v| v.as_u64()).unwrap_or_default();
+            let task = y
+                .get("task")
+                .and_then(|task| task.as_str())
+                .and_then(|task| serde_json::from_str(task).ok())
+                .ok_or_else(|| Unreadable::Corrupt("no task".to_string()))
+                .and_then(|task| schema::METADATA.load_at::<MetaData>(version as u32, task));
+
+            let (Ok(id), Ok(mut task)) = (serde_json::from_str::<Uuid>(id), task) else {
+                log(("skipping unreadable task: ", id));
+                continue;
+            };
+            task.assign_legacy_versions();
             online_tasks.insert(id, task);
         }
 
-        online_tasks
+        (online_tasks, server_time)
     }
 }
 
@@ -175,10 +686,10 @@ impl Task {
         }
     }
 
-    pub fn value(&self) -> f32 {
+    pub fn value(&self, now: UnixTime) -> f32 {
         self.metadata
             .value
-            .value(&self.log, self.metadata.created, utils::current_time())
+            .value(&self.log, self.metadata.created, now)
     }
 
     pub fn is_disc(&self) -> bool {
@@ -271,26 +782,60 @@ impl Task {
         panic!();
     }
 
-    pub fn do_task(&mut self, units: f32) {
-        let record = LogRecord::new_current(units);
-        self.log.new(record);
-        block_on(self.log.save_offline(self.id));
+    /// A completion at `time`, which may be in the past.
+    pub fn completion(&self, units: f32, time: UnixTime) -> LogRecord {
+        let mut record = LogRecord::new(time, units);
+        record.set_clock(clock::tick());
+        record.params = Some(self.metadata.value.clone());
+        record.earned = Some(self.earned(&record));
+        record
+    }
 
-        let state = use_context::<State>();
-        if let Some(user) = state.auth_user() {
-            let future = add_task_log_to_firestore(user.uid, self.id, record);
-            wasm_bindgen_futures::spawn_local(async {
-                match future.await {
-                    Ok(_) => web_sys::console::log_1(&JsValue::from_str("Log added successfully")),
-                    Err(e) => web_sys::console::log_1(&e),
-                }
-            });
+    /// Edited records keep the value equation they were logged with.
+    pub fn edited_record(&self, id: Uuid, time: UnixTime, units: f32) -> Option<LogRecord> {
+        let mut record = self.log.get(id)?;
+        record.time = time;
+        record.units = units;
+        record.bump(clock::tick());
+        record.earned = Some(self.earned(&record));
+        Some(record)
+    }
+
+    /// Every record, with what it earned recalculated using the current value equation.
+    pub fn recalculated(&self) -> Vec<LogRecord> {
+        let now = clock::tick();
+        let params = &self.metadata.value;
+        let mut acc = Accumulator::new(params);
+        let mut changed = vec![];
+
+        for record in self.log.records() {
+            let mut record = record.clone();
+            record.params = Some(params.clone());
+            record.earned =
+                Some(acc.earned(params, self.metadata.created, record.time, record.units));
+            acc.add(record.time, record.units);
+            record.bump(now);
+            changed.push(record);
         }
+
+        changed
     }
 
-    /// Hourly wage
-    pub fn priority(&self) -> f32 {
-        let now = utils::current_time();
+    /// What `record` earns, given the records logged before it.
+    fn earned(&self, record: &LogRecord) -> f32 {
+        let before: Vec<LogRecord> = self
+            .log
+            .records()
+            .filter(|rec| rec.time < record.time && rec.id != record.id)
+            .cloned()
+            .collect();
+
+        let params = record.params.as_ref().unwrap_or(&self.metadata.value);
+        params.earned(&TaskLog::newlol(before), record, self.metadata.created)
+    }
+
+    /// Hourly wage at `now`.
+    pub fn priority(&self, now: UnixTime) -> f32 {
         let val = self
             .metadata
             .value
@@ -302,180 +847,233 @@ impl Task {
 
     // Value accrued after 'dur'.
     pub fn value_since(&self, cutoff: UnixTime) -> f32 {
-        let mut value_accrued = 0.;
-        let tasklog = self.log.clone();
-
-        let mut inner = vec![];
-        for log in &tasklog.0 {
-            for _ in 0..(log.units as u32) {
-                let time = log.time;
-                if time > cutoff {
-                    let value = self.metadata.value.value(
-                        &TaskLog::newlol(inner.clone()),
-                        self.metadata.created,
-                        time,
-                    );
-
-                    value_accrued += value;
-                }
-                inner.push(log.clone());
-            }
-        }
-
-        value_accrued
+        self.log
+            .earned_since(cutoff, &self.metadata.value, self.metadata.created)
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct TaskLog(Vec<LogRecord>);
+#[serde(from = "Vec<LogRecord>", into = "Vec<LogRecord>")]
+pub struct TaskLog {
+    logs: Vec<LogRecord>,
+    // Built on first use, and kept up to date as records are appended.
+    earnings: RefCell<Option<Earnings>>,
+}
 
-impl TaskLog {
-    fn new(&mut self, record: LogRecord) {
-        if !self.0.contains(&record) {
-            self.0.push(record);
-        }
+impl From<Vec<LogRecord>> for TaskLog {
+    fn from(logs: Vec<LogRecord>) -> Self {
+        Self::newlol(logs)
     }
+}
 
-    pub fn time_since(&self, time: UnixTime) -> Vec<Duration> {
-        let mut vec = vec![];
+impl From<TaskLog> for Vec<LogRecord> {
+    fn from(log: TaskLog) -> Self {
+        log.logs
+    }
+}
 
-        for log in &self.0 {
-            vec.push(time - log.time);
+impl TaskLog {
+    /// Inserts the record, or replaces the one with the same id if this one is newer.
+    pub fn upsert(&mut self, record: LogRecord) {
+        match self.logs.iter_mut().find(|rec| rec.id == record.id) {
+            Some(existing) => {
+                if record.is_newer(existing) {
+                    *existing = record;
+                    self.earnings.take();
+                }
+            }
+            None => {
+                let appended = record.deleted || self.update_earnings(&record);
+                if !appended {
+                    self.earnings.take();
+                }
+                self.logs.push(record);
+            }
         }
 
-        vec
+        self.logs.sort_by_key(|rec| rec.time);
     }
 
-    pub fn last_completed(&self) -> Option<UnixTime> {
-        self.0.last().copied().map(|rec| rec.time)
+    /// Adds a new record to the earnings, if they're built and it's the latest one.
+    fn update_earnings(&self, record: &LogRecord) -> bool {
+        match self.earnings.borrow_mut().as_mut() {
+            Some(earnings) => earnings.push(record),
+            None => true,
+        }
     }
 
-    fn newlol(mut logs: Vec<LogRecord>) -> Self {
-        logs.sort_by_key(|log| log.time);
-        Self(logs)
-    }
+    /// What the records after `cutoff` earned.
+    pub fn earned_since(&self, cutoff: UnixTime, params: &ValueEq, created: UnixTime) -> f32 {
+        let mut earnings = self.earnings.borrow_mut();
 
-    pub async fn sync_id(id: TaskID, uid: String) -> LogSyncRes {
-        use crate::firebase;
+        if !earnings
+            .as_ref()
+            .is_some_and(|earnings| earnings.is_built_with(params, created))
+        {
+            *earnings = Some(Earnings::build(self.records(), params, created));
+        }
 
-        let offline_logs = TaskLog::load_logs(id).await;
-        let online_logs = {
-            let val = firebase::load_logs_for_task(uid.clone(), id).await.unwrap();
+        earnings.as_ref().unwrap().since(cutoff)
+    }
 
-            Self::from_jsvalue(val)
-        };
+    /// Every record, including the deleted ones.
+    pub fn all(&self) -> impl Iterator<Item = &LogRecord> {
+        self.logs.iter()
+    }
 
-        let mut res = TaskLog::sync(online_logs, offline_logs);
-        res.id = id;
-        res.user_id = uid;
-        res
+    /// The records that haven't been deleted.
+    pub fn records(&self) -> impl Iterator<Item = &LogRecord> {
+        self.logs.iter().filter(|rec| !rec.deleted)
     }
 
-    pub fn sync(from_online: Self, from_offline: Self) -> LogSyncRes {
-        let mut res = LogSyncRes::default();
-        let mut send_up = vec![];
-        let mut save = vec![];
+    pub fn get(&self, id: Uuid) -> Option<LogRecord> {
+        self.records().find(|rec| rec.id == id).cloned()
+    }
 
-        for unix in from_offline.0 {
-            if !from_online.0.contains(&unix) {
-                send_up.push(unix);
-            }
+    /// Deletes the record as of `clock`. Unless something newer brings it back, the record stays
+    /// deleted even if we only get it afterwards.
+    pub fn tombstone(&mut self, id: Uuid, clock: Hlc) {
+        let mut record = self
+            .logs
+            .iter()
+            .find(|rec| rec.id == id)
+            .cloned()
+            .unwrap_or_else(|| LogRecord {
+                id,
+                ..Default::default()
+            });
+        record.deleted = true;
+        record.set_clock(clock);
+        self.upsert(record);
+    }
+
+    /// The newest change to any of the records, including deleted ones.
+    pub fn newest_clock(&self) -> Option<Hlc> {
+        self.all().map(LogRecord::clock).max()
+    }
 
-            if !save.contains(&unix) {
-                save.push(unix);
+    /// Gives logs stored before records had ids their legacy id.
+    pub fn assign_legacy_ids(&mut self) {
+        for rec in &mut self.logs {
+            if rec.id.is_nil() {
+                rec.id = LogRecord::legacy_id(rec.time, rec.units);
             }
         }
+    }
 
-        for unix in from_online.0 {
-            if !save.contains(&unix) {
-                save.push(unix);
-            }
+    pub fn time_since(&self, time: UnixTime) -> Vec<Duration> {
+        let mut vec = vec![];
+
+        for log in self.records() {
+            vec.push(time - log.time);
         }
 
-        send_up.sort_by_key(|rec| rec.time);
+        vec
+    }
 
-        res.send_up = send_up;
-        res.save = Self::newlol(save);
+    pub fn last_completed(&self) -> Option<UnixTime> {
+        self.records().map(|rec| rec.time).max()
+    }
 
-        res
+    fn newlol(mut logs: Vec<LogRecord>) -> Self {
+        logs.sort_by_key(|log| log.time);
+        Self {
+            logs,
+            earnings: RefCell::default(),
+        }
     }
 
+    /// Parses the log documents of a task, including the ones stored in the legacy format.
     pub fn from_jsvalue(val: JsValue) -> Self {
-        let mut logs = vec![];
         let val: serde_json::Value = serde_wasm_bindgen::from_value(val).unwrap();
-        let arr = val.as_array().unwrap().clone();
+        Self::from_docs(val.as_array().unwrap())
+    }
+
+    /// Reads log documents as stored on the server before the journal.
+    pub fn from_docs(arr: &[serde_json::Value]) -> Self {
+        let mut logs = vec![];
+
+        let millis = |obj: &serde_json::Map<String, serde_json::Value>, key: &str| {
+            obj.get(key)
+                .and_then(|val| val.as_str())
+                .map(|val| UnixTime::from_millis(val.parse().unwrap()))
+        };
 
         for el in arr {
-            let ts: u64 = el
-                .as_object()
-                .unwrap()
-                .get("timestamp")
-                .unwrap()
-                .as_str()
-                .unwrap()
-                .parse()
-                .unwrap();
-
-            let ts = UnixTime::from_secs(ts);
-
-            let units: f32 = match el.as_object().unwrap().get("units").unwrap().as_str() {
+            let obj = el.as_object().unwrap();
+            if obj.get("journal").and_then(|j| j.as_bool()) == Some(true) {
+                continue;
+            }
+            let doc_id = obj.get("timestamp").unwrap().as_str().unwrap();
+
+            let units: f32 = match obj.get("units").unwrap().as_str() {
                 Some(s) => s.parse().unwrap(),
                 None => 1.,
             };
 
-            let log = LogRecord::new(ts, units);
-            logs.push(log);
-        }
+            let deleted = obj
+                .get("deleted")
+                .and_then(|d| d.as_bool())
+                .unwrap_or(false);
+
+            // Legacy documents are keyed by the time in seconds.
+            if let Ok(secs) = doc_id.parse::<u64>() {
+                let ts = UnixTime::from_secs(secs);
+                let mut log = LogRecord::new(ts, units);
+                log.id = LogRecord::legacy_id(ts, units);
+                log.deleted = deleted;
+                if let Some(updated) = obj.get("updated").and_then(|u| u.as_str()) {
+                    log.updated = UnixTime::from_secs(updated.parse().unwrap());
+                }
 
-        logs.sort_by_key(|log| log.time);
-        Self(logs)
-    }
+                logs.push(log);
+                continue;
+            }
 
-    fn merge(&mut self, other: Self) {
-        let mut merged = vec![];
+            let time = millis(obj, "time").unwrap();
+            let log = LogRecord {
+                id: doc_id.parse().unwrap(),
+                time,
+                units,
+                updated: millis(obj, "updated").unwrap_or(time),
+                counter: obj
+                    .get("counter")
+                    .and_then(|val| val.as_str())
+                    .map(|val| val.parse().unwrap())
+                    .unwrap_or_default(),
+                deleted,
+                earned: obj
+                    .get("earned")
+                    .and_then(|val| val.as_str())
+                    .map(|val| val.parse().unwrap()),
+                params: obj
+                    .get("params")
+                    .and_then(|val| val.as_str())
+                    .and_then(|val| serde_json::from_str(val).ok()),
+            };
 
-        for log in &self.0 {
-            if !merged.contains(log) {
-                merged.push(*log);
-            }
+            logs.push(log);
         }
 
-        for log in &other.0 {
-            if !merged.contains(log) {
-                merged.push(*log);
-            }
+        let mut log = Self::default();
+        for record in logs {
+            log.upsert(record);
         }
-
-        merged.sort_by_key(|mrg| mrg.time);
-
-        *self = Self(merged);
+        log
     }
 
-    pub async fn load_logs(task: TaskID) -> Self {
-        cache::fetch_logs()
-            .await
-            .get(&task)
-            .cloned()
-            .unwrap_or_default()
-    }
-
-    pub async fn save_offline(&self, id: TaskID) {
-        let mut all_logs = cache::fetch_logs().await;
-        let mut current = all_logs.get(&id).cloned().unwrap_or_default();
-        current.merge(self.clone());
-        all_logs.insert(id, current);
-
-        log("starting save logs");
-        cache::save_logs(all_logs);
-        log_to_console("Stored logs in local storage");
+    pub fn merge(&mut self, other: Self) {
+        for log in other.logs {
+            self.upsert(log);
+        }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct LogPriority {
-    interval: UnixTime,
-    factor: f32,
-    slope: f32,
+    pub interval: UnixTime,
+    pub factor: f32,
+    pub slope: f32,
 }
 
 impl LogPriority {
@@ -487,7 +1085,7 @@ impl LogPriority {
         }
     }
 
-    fn value(&self, t: Duration) -> f32 {
+    pub fn value(&self, t: Duration) -> f32 {
         let ratio = t.as_secs_f32() / self.interval.as_secs_f32();
         self.factor * val_calc::value(ratio, self.slope)
     }
@@ -499,7 +1097,7 @@ pub mod val_calc {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum ValueEq {
     Log(LogPriority),
     Const(f32),
@@ -513,23 +1111,28 @@ impl ValueEq {
             Self::Cont(c) => c.value(logs, current_time),
             Self::Log(log) => {
                 let last_completed = logs.last_completed().unwrap_or(created - log.interval);
-                let time_since = current_time - last_completed;
+                let time_since = current_time.saturating_sub(last_completed);
                 log.value(time_since)
             }
         }
     }
+
+    /// The value of doing `record`, one unit at a time, given the logs before it.
+    pub fn earned(&self, before: &TaskLog, record: &LogRecord, created: UnixTime) -> f32 {
+        Accumulator::replay(self, before.records()).earned(self, created, record.time, record.units)
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct Contask {
     // How many units you're expected to do per day on avg
-    daily_units: f32,
+    pub daily_units: f32,
     //  when you do one unit at the average rate, how much is the value?
-    factor: f32,
+    pub factor: f32,
 
-    created: UnixTime,
+    pub created: UnixTime,
 
-    unit_name: Option<String>,
+    pub unit_name: Option<String>,
 }
 
 impl Contask {
@@ -551,19 +1154,14 @@ impl Contask {
 
     fn ratio(&self, logs: &TaskLog, current: UnixTime) -> f32 {
         let avg = self.daily_average(logs, current, 0.8);
-        log(("avg: ", avg));
         self.daily_units / avg
     }
 
     fn daily_average(&self, logs: &TaskLog, current: UnixTime, lambda: f32) -> f32 {
-        let mut logs = logs.0.clone();
-        logs.insert(
-            0,
-            LogRecord {
-                units: self.daily_units,
-                time: self.created,
-            },
-        );
+        let mut logs: Vec<LogRecord> = logs.records().cloned().collect();
+        logs.insert(0, LogRecord::new(self.created, self.daily_units));
+        // Backdated logs can be older than the task itself.
+        logs.sort_by_key(|log| log.time);
 
         let day_stuff = day_stuff(&logs, current);
 
@@ -626,26 +1224,11 @@ mod tests {
 
     fn dummylogs() -> Vec<LogRecord> {
         vec![
-            LogRecord {
-                time: UnixTime::from_secs(86400 * 1),
-                units: 10.0,
-            },
-            LogRecord {
-                time: UnixTime::from_secs(86400 * 2 + 10),
-                units: 20.0,
-            },
-            LogRecord {
-                time: UnixTime::from_secs(86400 * 2),
-                units: 20.0,
-            },
-            LogRecord {
-                time: UnixTime::from_secs(86400 * 3),
-                units: 30.0,
-            },
-            LogRecord {
-                time: UnixTime::from_secs(86400 * 5),
-                units: 60.0,
-            },
+            LogRecord::new(UnixTime::from_secs(86400 * 1), 10.0),
+            LogRecord::new(UnixTime::from_secs(86400 * 2 + 10), 20.0),
+            LogRecord::new(UnixTime::from_secs(86400 * 2), 20.0),
+            LogRecord::new(UnixTime::from_secs(86400 * 3), 30.0),
+            LogRecord::new(UnixTime::from_secs(86400 * 5), 60.0),
         ]
     }
 
@@ -658,12 +1241,232 @@ mod tests {
         assert_eq!(result, vec![10.0, 40.0, 30.0, 0.0, 60.0, 0.0, 0.0, 0.0]);
     }
 
+    #[test]
+    fn test_deleted_log_survives_sync() {
+        let record = LogRecord::new(UnixTime::from_secs(1000), 1.);
+        let online = TaskLog::newlol(vec![record.clone()]);
+
+        let mut offline = online.clone();
+        offline.tombstone(record.id, UnixTime::from_secs(2000).into());
+
+        offline.merge(online);
+        assert!(offline.get(record.id).is_none());
+        assert_eq!(offline.records().count(), 0);
+        assert_eq!(offline.all().count(), 1);
+    }
+
+    fn edit(log: &mut TaskLog, id: Uuid, time: UnixTime, units: f32, now: Hlc) {
+        let mut record = log.get(id).unwrap();
+        record.time = time;
+        record.units = units;
+        record.bump(now);
+        log.upsert(record);
+    }
+
+    #[test]
+    fn test_edit_log() {
+        let time = UnixTime::from_secs(1000);
+        let new_time = UnixTime::from_secs(500);
+        let record = LogRecord::new(time, 1.);
+        let mut log = TaskLog::newlol(vec![record.clone()]);
+
+        // Editing in the same millisecond as the record was created must still win.
+        edit(&mut log, record.id, time, 3., time.into());
+        assert_eq!(log.get(record.id).unwrap().units, 3.);
+
+        edit(&mut log, record.id, new_time, 2., time.into());
+        assert_eq!(log.get(record.id).unwrap().units, 2.);
+        assert_eq!(log.last_completed(), Some(new_time));
+
+        // An older copy from another device must not bring the old version back.
+        let mut stale = TaskLog::newlol(vec![record]);
+        stale.merge(log);
+        assert_eq!(stale.records().count(), 1);
+        assert_eq!(stale.last_completed(), Some(new_time));
+    }
+
+    #[test]
+    fn test_same_second_logs() {
+        let time = UnixTime::from_millis(1_000_100);
+        let mut log = TaskLog::default();
+        log.upsert(LogRecord::new(time, 1.));
+        log.upsert(LogRecord::new(time, 1.));
+        assert_eq!(log.records().count(), 2);
+
+        let mut other = TaskLog::default();
+        other.merge(log);
+        assert_eq!(other.records().count(), 2);
+    }
+
+    #[test]
+    fn test_legacy_ids() {
+        let time = UnixTime::from_secs(1000);
+        let mut legacy = LogRecord::new(time, 2.);
+        legacy.id = Uuid::nil();
+
+        let mut local = TaskLog::newlol(vec![legacy]);
+        local.assign_legacy_ids();
+        local.assign_legacy_ids();
+
+        // The same log, as parsed from a legacy document on the server.
+        let mut remote = LogRecord::new(time, 2.);
+        remote.id = LogRecord::legacy_id(time, 2.);
+
+        local.merge(TaskLog::newlol(vec![remote.clone()]));
+        assert_eq!(local.records().count(), 1);
+        assert_eq!(local.get(remote.id).unwrap().units, 2.);
+    }
+
+    #[test]
+    fn test_backdated_log() {
+        let mut log = TaskLog::default();
+        log.upsert(LogRecord::new(UnixTime::from_secs(2000), 1.));
+        log.upsert(LogRecord::new(UnixTime::from_secs(1000), 1.));
+        assert_eq!(log.last_completed(), Some(UnixTime::from_secs(2000)));
+
+        // Logged before the task was created.
+        let contask = Contask {
+            daily_units: 1.,
+            factor: 1.,
+            created: UnixTime::from_secs(86400 * 3),
+            unit_name: None,
+        };
+        let avg = contask.daily_average(&log, UnixTime::from_secs(86400 * 4), 0.8);
+        assert!(avg > 0.);
+    }
+
+    #[test]
+    fn test_earnings_survive_edits() {
+        let mut task = Task {
+            id: Uuid::nil(),
+            log: TaskLog::default(),
+            metadata: dishes(),
+        };
+
+        let mut record = LogRecord::new(UnixTime::from_secs(86400 * 2), 1.);
+        record.params = Some(task.metadata.value.clone());
+        record.earned = Some(task.earned(&record));
+        task.log.upsert(record);
+
+        let earned = task.value_since(UnixTime::ZERO);
+        assert!(earned > 0.);
+
+        task.set_factor(20.);
+        assert_eq!(task.value_since(UnixTime::ZERO), earned);
+    }
+
+    #[test]
+    fn test_value_logged_in_future() {
+        let metadata = dishes();
+        let future = LogRecord::new(UnixTime::from_secs(86400 * 3), 1.);
+        let log = TaskLog::newlol(vec![future]);
+        let now = UnixTime::from_secs(86400 * 2);
+        assert!(metadata
+            .value
+            .value(&log, metadata.created, now)
+            .is_finite());
+    }
+
+    #[test]
+    fn test_legacy_doc() {
+        let record = LogRecord::new(UnixTime::from_millis(86_400_250), 2.);
+        let (id, fields) = record.legacy_doc();
+        assert_eq!(id, "86400");
+        assert_eq!(fields["units"], "2");
+
+        // Copies of what the journal has are left out when importing.
+        let legacy = serde_json::json!({"timestamp": "86400", "units": "2"});
+        let mut copy = fields.clone();
+        copy["timestamp"] = id.into();
+        assert_eq!(TaskLog::from_docs(&[legacy, copy]).records().count(), 1);
+    }
+
+    fn dishes() -> MetaData {
+        MetaData::test("dishes", version(86400, Uuid::nil()))
+    }
+
+    fn version(secs: u64, device: Uuid) -> Version {
+        Version::new(UnixTime::from_secs(secs).into(), device)
+    }
+
+    fn merged(a: &MetaData, b: &MetaData) -> MetaData {
+        let mut merged = a.clone();
+        merged.merge(b.clone());
+        merged
+    }
+
+    #[test]
+    fn test_metadata_field_merge() {
+        let base = dishes();
+        let (phone, laptop) = (Uuid::from_u128(1), Uuid::from_u128(2));
+
+        let mut renamed = base.clone();
+        renamed.apply(
+            Field::Name("do the dishes".to_string()),
+            version(86400 * 2, phone),
+        );
+
+        let mut rescheduled = base.clone();
+        rescheduled.apply(
+            Field::Value(ValueEq::Log(LogPriority::new(
+                10.,
+                UnixTime::from_secs(86400 * 2),
+            ))),
+            version(86400 * 3, laptop),
+        );
+
+        let both = merged(&renamed, &rescheduled);
+        assert_eq!(both.name, "do the dishes");
+        assert_eq!(both.value, rescheduled.value);
+        assert_eq!(both, merged(&rescheduled, &renamed));
+    }
+
+    #[test]
+    fn test_metadata_same_time_edits() {
+        let base = dishes();
+        let time = UnixTime::from_millis(86400 * 2000);
+
+        let mut a = base.clone();
+        a.apply(
+            Field::Name("a".to_string()),
+            Version::new(time.into(), Uuid::from_u128(1)),
+        );
+
+        let mut b = base.clone();
+        b.apply(
+            Field::Name("b".to_string()),
+            Version::new(time.into(), Uuid::from_u128(2)),
+        );
+
+        assert_eq!(merged(&a, &b).name, "b");
+        assert_eq!(merged(&b, &a).name, "b");
+    }
+
+    #[test]
+    fn test_deletion_beats_edits_until_restored() {
+        let base = dishes();
+        let (phone, laptop) = (Uuid::from_u128(1), Uuid::from_u128(2));
+
+        let mut deleted = base.clone();
+        deleted.apply(Field::Deleted(true), version(86400 * 2, phone));
+
+        let mut renamed = base.clone();
+        renamed.apply(
+            Field::Name("do the dishes".to_string()),
+            version(86400 * 3, laptop),
+        );
+
+        let mut both = merged(&renamed, &deleted);
+        assert!(both.deleted);
+        assert_eq!(both.name, "do the dishes");
+
+        both.apply(Field::Deleted(false), version(86400 * 4, laptop));
+        assert!(!merged(&deleted, &both).deleted);
+    }
+
     #[test]
     fn loltest_avg_stuff() {
-        let logs = vec![LogRecord {
-            time: UnixTime::from_secs(0),
-            units: 10.,
-        }];
+        let logs = vec![LogRecord::new(UnixTime::from_secs(0), 10.)];
 
         let decay = 0.8;
         let mut prev = 3.5073876 / decay;
@@ -0,0 +1,335 @@
+use crate::clock::Hlc;
+use crate::journal::{Op, OpKind};
+use crate::task::{Field, LogRecord, Task, TaskID};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// Older actions are forgotten.
+const MAX_ACTIONS: usize = 50;
+
+/// A part of a task that an operation changes, along with its value.
+#[derive(Debug, Clone)]
+pub enum Part {
+    Field(Field),
+    // None if the record doesn't exist or is deleted.
+    Record(Uuid, Option<LogRecord>),
+}
+
+impl Part {
+    /// The part an operation changes. The value is whatever the operation sets it to.
+    fn touched_by(op: &Op) -> Self {
+        match &op.kind {
+            OpKind::Created(_) | OpKind::Restored => Self::Field(Field::Deleted(false)),
+            OpKind::Deleted => Self::Field(Field::Deleted(true)),
+            OpKind::Edited(field) => Self::Field(field.clone()),
+            OpKind::Logged(record) => Self::Record(record.id, Some(record.clone())),
+            OpKind::LogDeleted(id) => Self::Record(*id, None),
+        }
+    }
+
+    /// The same part, as it currently is on `task`. A task that doesn't exist counts as deleted.
+    fn read(&self, task: Option<&Task>) -> Self {
+        match self {
+            Self::Field(field) => Self::Field(match task {
+                Some(task) => task.metadata.field(field),
+                None => Field::Deleted(true),
+            }),
+            Self::Record(id, _) => Self::Record(*id, task.and_then(|task| task.log.get(*id))),
+        }
+    }
+
+    /// Whether the values are the same, regardless of when they were set.
+    fn same(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Field(a), Self::Field(b)) => a == b,
+            (Self::Record(a, x), Self::Record(b, y)) => {
+                a == b
+                    && match (x, y) {
+                        (Some(x), Some(y)) => x.time == y.time && x.units == y.units,
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }
+            _ => false,
+        }
+    }
+
+    /// An operation that sets the part back to this value.
+    pub fn into_kind(self, now: Hlc) -> OpKind {
+        match self {
+            Self::Field(Field::Deleted(true)) => OpKind::Deleted,
+            Self::Field(Field::Deleted(false)) => OpKind::Restored,
+            Self::Field(field) => OpKind::Edited(field),
+            Self::Record(id, None) => OpKind::LogDeleted(id),
+            Self::Record(_, Some(mut record)) => {
+                record.deleted = false;
+                record.bump(now);
+                OpKind::Logged(record)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Change {
+    task: TaskID,
+    before: Part,
+    after: Part,
+}
+
+/// Something the user did, as the parts of tasks it changed.
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub label: String,
+    changes: Vec<Change>,
+}
+
+impl Action {
+    /// Call before applying `ops`, then call `finish` after.
+    pub fn start(tasks: &HashMap<TaskID, Task>, ops: &[Op]) -> Self {
+        let changes = ops
+            .iter()
+            .map(|op| {
+                let part = Part::touched_by(op);
+                Change {
+                    task: op.task,
+                    before: part.read(tasks.get(&op.task)),
+                    after: part,
+                }
+            })
+            .collect();
+
+        Self {
+            label: String::new(),
+            changes,
+        }
+    }
+
+    pub fn finish(mut self, tasks: &HashMap<TaskID, Task>, ops: &[Op]) -> Self {
+        for change in &mut self.changes {
+            change.after = change.after.read(tasks.get(&change.task));
+        }
+        self.label = ops
+            .first()
+            .map(|op| describe(op, tasks.get(&op.task)))
+            .unwrap_or_default();
+        self
+    }
+
+    /// The parts to set back to how they were before the action. Parts that have been changed
+    /// since, say by another device, are left alone.
+    fn undo(&self, tasks: &HashMap<TaskID, Task>) -> Vec<(TaskID, Part)> {
+        self.changes
+            .iter()
+            .filter(|change| {
+                change
+                    .after
+                    .same(&change.after.read(tasks.get(&change.task)))
+            })
+            .map(|change| (change.task, change.before.clone()))
+            .collect()
+    }
+
+    fn redo(&self, tasks: &HashMap<TaskID, Task>) -> Vec<(TaskID, Part)> {
+        self.changes
+            .iter()
+            .filter(|change| {
+                change
+                    .before
+                    .same(&change.before.read(tasks.get(&change.task)))
+            })
+            .map(|change| (change.task, change.after.clone()))
+            .collect()
+    }
+}
+
+fn describe(op: &Op, task: Option<&Task>) -> String {
+    let name = task
+        .map(|task| task.metadata.name.as_str())
+        .unwrap_or("a task");
+    match op.kind {
+        OpKind::Created(_) => format!("created {}", name),
+        OpKind::Edited(_) => format!("edited {}", name),
+        OpKind::Logged(_) => format!("logged {}", name),
+        OpKind::LogDeleted(_) => format!("deleted a log of {}", name),
+        OpKind::Deleted => format!("deleted {}", name),
+        OpKind::Restored => format!("restored {}", name),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Done,
+    Undone,
+    Redone,
+}
+
+/// The last thing that happened to the history, for showing it to the user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notice {
+    // Increases with every event.
+    pub seq: u64,
+    pub event: Event,
+    pub label: String,
+}
+
+/// Undo and redo stacks. Undoing records new operations rather than removing old ones, so it
+/// syncs like any other change.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    undo: Vec<Action>,
+    redo: Vec<Action>,
+    notice: Option<Notice>,
+}
+
+impl History {
+    pub fn notice(&self) -> Option<&Notice> {
+        self.notice.as_ref()
+    }
+
+    fn notify(&mut self, event: Event, label: String) {
+        let seq = self.notice.as_ref().map_or(0, |notice| notice.seq + 1);
+        self.notice = Some(Notice { seq, event, label });
+    }
+
+    pub fn push(&mut self, action: Action) {
+        self.notify(Event::Done, action.label.clone());
+        self.undo.push(action);
+        if self.undo.len() > MAX_ACTIONS {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    /// Returns the parts to change, see `Part::into_kind`.
+    pub fn undo(&mut self, tasks: &HashMap<TaskID, Task>) -> Vec<(TaskID, Part)> {
+        let Some(action) = self.undo.pop() else {
+            return vec![];
+        };
+
+        let parts = action.undo(tasks);
+        self.notify(Event::Undone, action.label.clone());
+        self.redo.push(action);
+        parts
+    }
+
+    pub fn redo(&mut self, tasks: &HashMap<TaskID, Task>) -> Vec<(TaskID, Part)> {
+        let Some(action) = self.redo.pop() else {
+            return vec![];
+        };
+
+        let parts = action.redo(tasks);
+        self.notify(Event::Redone, action.label.clone());
+        self.undo.push(action);
+        parts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::apply_all;
+    use crate::task::{MetaData, Version};
+    use std::time::Duration;
+
+    type UnixTime = Duration;
+
+    const TASK: Uuid = Uuid::from_u128(7);
+    const PHONE: Uuid = Uuid::from_u128(1);
+    const LAPTOP: Uuid = Uuid::from_u128(2);
+
+    fn version(secs: u64, device: Uuid) -> Version {
+        Version::new(UnixTime::from_secs(secs).into(), device)
+    }
+
+    fn created() -> Op {
+        let version = version(100, PHONE);
+        let metadata = MetaData::test("dishes", version);
+        Op::new(TASK, version, OpKind::Created(Box::new(metadata)))
+    }
+
+    /// Applies `ops` the way `Tasks` does, recording them in `history`.
+    fn act(tasks: &mut HashMap<TaskID, Task>, history: &mut History, ops: Vec<Op>) {
+        let action = Action::start(tasks, &ops);
+        apply_all(tasks, &ops);
+        history.push(action.finish(tasks, &ops));
+    }
+
+    fn revert(tasks: &mut HashMap<TaskID, Task>, parts: Vec<(TaskID, Part)>, secs: u64) -> usize {
+        let now: Hlc = UnixTime::from_secs(secs).into();
+        let ops: Vec<Op> = parts
+            .into_iter()
+            .map(|(task, part)| Op::new(task, Version::new(now, PHONE), part.into_kind(now)))
+            .collect();
+        apply_all(tasks, &ops);
+        ops.len()
+    }
+
+    fn name(tasks: &HashMap<TaskID, Task>) -> &str {
+        &tasks[&TASK].metadata.name
+    }
+
+    #[test]
+    fn test_undo_redo_edit() {
+        let mut tasks = HashMap::default();
+        let mut history = History::default();
+        act(&mut tasks, &mut history, vec![created()]);
+
+        let rename = Op::new(
+            TASK,
+            version(200, PHONE),
+            OpKind::Edited(Field::Name("plates".into())),
+        );
+        act(&mut tasks, &mut history, vec![rename]);
+        assert_eq!(history.notice().unwrap().label, "edited plates");
+
+        let parts = history.undo(&tasks);
+        revert(&mut tasks, parts, 300);
+        assert_eq!(name(&tasks), "dishes");
+
+        let parts = history.redo(&tasks);
+        revert(&mut tasks, parts, 400);
+        assert_eq!(name(&tasks), "plates");
+
+        // Undoing the creation deletes the task.
+        history.undo(&tasks);
+        let parts = history.undo(&tasks);
+        revert(&mut tasks, parts, 500);
+        assert!(tasks[&TASK].metadata.deleted);
+    }
+
+    #[test]
+    fn test_undo_after_sync() {
+        let mut tasks = HashMap::default();
+        let mut history = History::default();
+        act(&mut tasks, &mut history, vec![created()]);
+
+        let mut record = LogRecord {
+            id: Uuid::new_v4(),
+            time: UnixTime::from_secs(150),
+            units: 1.,
+            ..Default::default()
+        };
+        record.bump(UnixTime::from_secs(150).into());
+        let logged = Op::new(TASK, version(150, PHONE), OpKind::Logged(record.clone()));
+        let rename = Op::new(
+            TASK,
+            version(200, PHONE),
+            OpKind::Edited(Field::Name("plates".into())),
+        );
+        act(&mut tasks, &mut history, vec![logged, rename]);
+
+        // Another device renames the task again before we undo.
+        let remote = Op::new(
+            TASK,
+            version(250, LAPTOP),
+            OpKind::Edited(Field::Name("cups".into())),
+        );
+        apply_all(&mut tasks, &[remote]);
+
+        let parts = history.undo(&tasks);
+        assert_eq!(revert(&mut tasks, parts, 300), 1);
+        assert_eq!(name(&tasks), "cups");
+        assert_eq!(tasks[&TASK].log.records().count(), 0);
+    }
+}
@@ -1,14 +1,64 @@
 use crate::task::TaskLog;
 use js_sys::Date;
 use std::time::Duration;
+use wasm_bindgen::JsValue;
 
 type UnixTime = Duration;
 
 pub fn current_time() -> UnixTime {
     let date = Date::new_0();
     let milliseconds_since_epoch = date.get_time() as u64;
-    let seconds_since_epoch = milliseconds_since_epoch / 1000;
-    UnixTime::from_secs(seconds_since_epoch)
+    UnixTime::from_millis(milliseconds_since_epoch)
+}
+
+/// Whether the browser thinks it's online. Assumed so if it can't tell.
+pub fn is_online() -> bool {
+    gloo::utils::window()
+        .get("navigator")
+        .and_then(|navigator| js_sys::Reflect::get(&navigator, &"onLine".into()).ok())
+        .and_then(|online| online.as_bool())
+        .unwrap_or(true)
+}
+
+fn js_date(time: UnixTime) -> Date {
+    Date::new(&JsValue::from_f64(time.as_millis() as f64))
+}
+
+/// Formats a time the way `<input type="datetime-local">` expects it, in local time.
+pub fn datetime_input_str(time: UnixTime) -> String {
+    let date = js_date(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}",
+        date.get_full_year(),
+        date.get_month() + 1,
+        date.get_date(),
+        date.get_hours(),
+        date.get_minutes()
+    )
+}
+
+pub fn parse_datetime_input(s: &str) -> Option<UnixTime> {
+    let millis = Date::new(&JsValue::from_str(s)).get_time();
+    if millis.is_nan() || millis < 0. {
+        return None;
+    }
+
+    Some(UnixTime::from_millis(millis as u64))
+}
+
+/// When something was done, from a datetime input that's left empty for now. Times that can't
+/// be read or are in the future are refused, with why.
+pub fn parse_when_input(s: &str) -> Result<UnixTime, &'static str> {
+    let now = current_time();
+    if s.trim().is_empty() {
+        return Ok(now);
+    }
+
+    match parse_datetime_input(s) {
+        Some(time) if time <= now => Ok(time),
+        Some(_) => Err("that's in the future"),
+        None => Err("that's not a valid time"),
+    }
 }
 
 pub fn dur_format(dur: Duration) -> String {